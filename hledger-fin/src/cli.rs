@@ -1,19 +1,107 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
-use crate::command;
+use crate::{
+    command,
+    journal::BuildOptions,
+    model::Date,
+    output::Split,
+    render::OutputFormat,
+    report::{realized::Period, ReportFormat},
+};
 
 #[derive(Subcommand)]
 pub enum Command {
     /// Print journal
-    Print,
+    Print {
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+
+        /// Assert the running balance on every cash and position posting. Balances
+        /// accumulate in the order the output format checks them: by date for
+        /// hledger, in file order for Ledger
+        #[arg(long)]
+        balance_assertions: bool,
+
+        /// Annotate every position posting with its lot cost and date, always on
+        /// for formats that need it
+        #[arg(long)]
+        lot_costs: bool,
+
+        /// Declare accounts with their types and commodities with their display
+        /// precision ahead of the entries, as `hledger --strict` requires. Other
+        /// text formats always declare them
+        #[arg(long)]
+        declarations: bool,
+
+        /// Interleave `P` market price directives derived from trades and prices
+        #[arg(long)]
+        prices: bool,
+
+        /// Write `P` market price directives to this file instead
+        #[arg(long, value_name = "FILE", conflicts_with = "prices")]
+        prices_file: Option<PathBuf>,
+
+        /// Write the journal to this file, only when its content changes
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Split the journal into one file per part, included from the output file
+        #[arg(long, value_enum, requires = "output")]
+        split: Option<Split>,
+    },
+    /// Check resources for semantic mistakes
+    Validate,
+    /// Show open positions and their lots
+    Holdings {
+        /// Only replay transactions up to this date (inclusive)
+        #[arg(long, value_name = "DATE")]
+        as_of: Option<Date>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+    },
+    /// Show realized gains of every sale, with totals
+    Realized {
+        /// Only include sales on or after this date
+        #[arg(long, value_name = "DATE")]
+        from: Option<Date>,
+
+        /// Only include sales on or before this date
+        #[arg(long, value_name = "DATE")]
+        to: Option<Date>,
+
+        /// Period to total gains by
+        #[arg(long, value_enum, default_value_t)]
+        period: Period,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+    },
+    /// Value open positions at market prices
+    Unrealized {
+        /// Replay transactions and look up prices up to this date (inclusive)
+        #[arg(long, value_name = "DATE")]
+        as_of: Option<Date>,
+
+        /// Import prices from the `P` directives of an hledger file (can be repeated)
+        #[arg(long, value_name = "FILE")]
+        prices: Vec<PathBuf>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+    },
 }
 
 #[derive(Parser)]
 pub struct Cli {
-    /// Set input resource file
-    #[arg(short, long, value_name = "FILE")]
-    file: PathBuf,
+    /// Set input resource file, directory or glob pattern (can be repeated)
+    #[arg(short, long, value_name = "FILE", required = true)]
+    file: Vec<PathBuf>,
 
     #[command(subcommand)]
     command: Command,
@@ -22,7 +110,45 @@ pub struct Cli {
 impl Cli {
     pub fn exec(self) {
         match self.command {
-            Command::Print => command::print_journal(self.file),
+            Command::Print {
+                format,
+                balance_assertions,
+                lot_costs,
+                declarations,
+                prices,
+                prices_file,
+                output,
+                split,
+            } => {
+                let options = BuildOptions {
+                    balance_assertions,
+                    lot_costs: lot_costs || format.requires_lot_costs(),
+                    assertion_order: format.assertion_order(split),
+                    ..Default::default()
+                };
+                command::print_journal(
+                    &self.file,
+                    format.renderer(declarations).as_ref(),
+                    &options,
+                    prices,
+                    prices_file.as_deref(),
+                    output.as_deref(),
+                    split,
+                )
+            }
+            Command::Validate => command::validate(&self.file),
+            Command::Holdings { as_of, format } => command::holdings(&self.file, as_of, format),
+            Command::Realized {
+                from,
+                to,
+                period,
+                format,
+            } => command::realized(&self.file, from, to, period, format),
+            Command::Unrealized {
+                as_of,
+                prices,
+                format,
+            } => command::unrealized(&self.file, as_of, &prices, format),
         }
     }
 }
@@ -1,12 +1,170 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::{hledger::HLedgerShow, input, journal};
+use crate::{
+    input,
+    input::Resource,
+    journal::{self, BuildOptions, JournalBuild},
+    model::{port::CashBalancePortfolio, price::Price, Date},
+    output::{self, Journal, OutputError, Split},
+    render::Renderer,
+    report::{self, realized::Period, ReportFormat},
+    validate,
+};
 
-pub fn print_journal<P: AsRef<Path>>(path: P) {
-    let resources = input::from_file(path).unwrap();
-    let entries = journal::build_journal(resources);
-    for e in entries {
-        let s = e.hledger_show();
-        println!("{s}\n");
+/// Print the journal, or write it to `output` split into parts. `P` directives
+/// are either placed among the entries or written to `prices_file`.
+pub fn print_journal<P: AsRef<Path>>(
+    paths: &[P],
+    renderer: &dyn Renderer,
+    options: &BuildOptions,
+    prices: bool,
+    prices_file: Option<&Path>,
+    output: Option<&Path>,
+    split: Option<Split>,
+) {
+    let resources = load_resources(paths);
+    let portfolios: Vec<CashBalancePortfolio> = resources
+        .iter()
+        .filter_map(|r| match r {
+            Resource::CashBalancePortfolio(port) => Some(port.as_ref().clone()),
+            _ => None,
+        })
+        .collect();
+    let market_prices = if prices || prices_file.is_some() {
+        journal::market_prices(&resources)
+    } else {
+        Vec::new()
+    };
+    let build = replay(resources, options);
+
+    let (placed_prices, external_prices): (&[Price], &[Price]) = match prices_file {
+        Some(prices_file) => {
+            exit_on_output_error(output::write_if_changed(
+                prices_file,
+                &renderer.prices(&market_prices),
+            ));
+            (&[], &market_prices)
+        }
+        None => (&market_prices, &[]),
+    };
+    let journal = Journal {
+        portfolios: &portfolios,
+        entries: &build.entries,
+        sections: &build.sections,
+        prices: placed_prices,
+        external_prices,
+    };
+    match output {
+        Some(output) => {
+            exit_on_output_error(output::write_journal(renderer, &journal, output, split))
+        }
+        None => print!("{}", journal.render(renderer)),
+    }
+}
+
+fn exit_on_output_error(result: Result<(), OutputError>) {
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
+
+pub fn validate<P: AsRef<Path>>(paths: &[P]) {
+    let resources = load_resources(paths);
+    let findings = validate::validate(&resources);
+    for f in &findings {
+        println!("{f}");
+    }
+    if findings.is_empty() {
+        println!("no problems found");
+    } else {
+        println!("{} problem(s) found", findings.len());
+        std::process::exit(1);
+    }
+}
+
+pub fn holdings<P: AsRef<Path>>(paths: &[P], as_of: Option<Date>, format: ReportFormat) {
+    let resources = load_resources(paths);
+    let build = replay(
+        resources,
+        &BuildOptions {
+            as_of,
+            ..Default::default()
+        },
+    );
+    let rows = report::holdings::holdings(&build.holdings);
+    print!("{}", report::holdings::render(&rows, format));
+}
+
+pub fn realized<P: AsRef<Path>>(
+    paths: &[P],
+    from: Option<Date>,
+    to: Option<Date>,
+    period: Period,
+    format: ReportFormat,
+) {
+    let resources = load_resources(paths);
+    let build = replay(
+        resources,
+        &BuildOptions {
+            as_of: to,
+            ..Default::default()
+        },
+    );
+    let report = report::realized::realized(&build.disposals, from, to, period);
+    print!("{}", report::realized::render(&report, format));
+}
+
+pub fn unrealized<P: AsRef<Path>>(
+    paths: &[P],
+    as_of: Option<Date>,
+    price_files: &[PathBuf],
+    format: ReportFormat,
+) {
+    let resources = load_resources(paths);
+    let mut prices: Vec<Price> = resources
+        .iter()
+        .filter_map(|r| match r {
+            Resource::Price(p) => Some(p.clone()),
+            _ => None,
+        })
+        .collect();
+    match input::prices_from_hledger(price_files) {
+        Ok(imported) => prices.extend(imported),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+    let build = replay(
+        resources,
+        &BuildOptions {
+            as_of,
+            ..Default::default()
+        },
+    );
+    let report = report::unrealized::unrealized(&build.holdings, &prices, as_of);
+    print!("{}", report::unrealized::render(&report, format));
+}
+
+/// Replay the journal, whose output is only meaningful without errors.
+fn replay(resources: Vec<Resource>, options: &BuildOptions) -> JournalBuild {
+    let build = journal::build(resources, options);
+    if !build.errors.is_empty() {
+        for e in build.errors {
+            eprintln!("error: {e}");
+        }
+        std::process::exit(1);
+    }
+    build
+}
+
+fn load_resources<P: AsRef<Path>>(paths: &[P]) -> Vec<Resource> {
+    match input::from_files(paths) {
+        Ok(resources) => resources,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
     }
 }
@@ -1,30 +1,489 @@
 use crate::model::{
     port::CashBalancePortfolio,
-    txn::{Buy, Deposit, InterestPayment, Sell, Withdraw},
-    PortId,
+    price::Price,
+    txn::{
+        Buy, Deposit, Dividend, FxConversion, InterestPayment, PortfolioTransfer, Sell, Split,
+        StockDividend, TransferIn, TransferOut, Withdraw,
+    },
+    Commodity, Date, PortId,
+};
+use rust_decimal::Decimal;
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    marker::PhantomData,
+    path::{Path, PathBuf},
 };
-use serde::{Deserialize, Serialize};
-use serde_yaml::Value;
-use std::path::Path;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ImportError {
-    #[error("unable to read file: {0}")]
-    FileIO(#[from] std::io::Error),
-    #[error("unable to parse resource: {0}")]
-    ParseError(String),
+    #[error("unable to read {}: {source}", .path.display())]
+    FileIO {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid glob pattern {pattern}: {source}")]
+    InvalidPattern {
+        pattern: String,
+        source: glob::PatternError,
+    },
+    #[error("no resource file matches {}", .0.display())]
+    NoMatch(PathBuf),
+    #[error(
+        "include cycle: {}",
+        .0.iter().map(|p| p.display().to_string()).collect::<Vec<String>>().join(" -> ")
+    )]
+    IncludeCycle(Vec<PathBuf>),
+    #[error("{}", .0.iter().map(|d| d.to_string()).collect::<Vec<String>>().join("\n\n"))]
+    ParseError(Vec<Diagnostic>),
+    #[error("{}:{line}: invalid price directive: {message}", .path.display())]
+    InvalidPriceDirective {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+/// A resource that failed to parse, located in its source file.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    /// 1-based index of the `---` document within the file
+    pub document: usize,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Failure reported by the parser, prefixed with the offending field path
+    pub message: String,
+    pub source_line: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(
+                f,
+                "  --> {}:{}:{} (document {})",
+                self.path.display(),
+                line,
+                column,
+                self.document
+            )?,
+            _ => write!(
+                f,
+                "  --> {} (document {})",
+                self.path.display(),
+                self.document
+            )?,
+        }
+        if let (Some(line), Some(column), Some(source_line)) =
+            (self.line, self.column, &self.source_line)
+        {
+            let gutter = " ".repeat(line.to_string().len());
+            write!(
+                f,
+                "\n{gutter} |\n{line} | {source_line}\n{gutter} | {}^",
+                " ".repeat(column.saturating_sub(1))
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostic {
+    fn new(path: &Path, yaml: &str, document: usize, error: serde_yaml::Error) -> Self {
+        let location = error.location();
+        let line = location.as_ref().map(|l| l.line());
+        let column = location.as_ref().map(|l| l.column());
+        // the parser appends the location to its message, it is rendered separately here
+        let message = error.to_string();
+        let message = match (line, column) {
+            (Some(line), Some(column)) => message
+                .strip_suffix(&format!(" at line {line} column {column}"))
+                .map(String::from)
+                .unwrap_or(message),
+            _ => message,
+        };
+        Self {
+            path: path.to_path_buf(),
+            document,
+            line,
+            column,
+            message,
+            source_line: line.and_then(|l| yaml.lines().nth(l - 1)).map(String::from),
+        }
+    }
+}
+
+/// Load resources from every input in order. An input is a file, a directory
+/// (every `.yaml`/`.yml` file below it) or a glob pattern. Matches are loaded in
+/// path order and `Include` resources are replaced by the resources they pull in.
+pub fn from_files<P: AsRef<Path>>(inputs: &[P]) -> Result<Vec<Resource>, ImportError> {
+    let mut loader = Loader::default();
+    for input in inputs {
+        for path in expand_input(input.as_ref())? {
+            loader.load_file(&path)?;
+        }
+    }
+    if loader.diagnostics.is_empty() {
+        Ok(loader.resources)
+    } else {
+        Err(ImportError::ParseError(loader.diagnostics))
+    }
+}
+
+/// Read market prices from the `P` directives of hledger journal or price files.
+/// Every other line is ignored, so a whole journal can be given.
+pub fn prices_from_hledger<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<Price>, ImportError> {
+    let mut prices = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ImportError::FileIO {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        for (idx, line) in text.lines().enumerate() {
+            let Some(directive) = line
+                .strip_prefix('P')
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+            else {
+                continue;
+            };
+            let price = parse_price_directive(directive).map_err(|message| {
+                ImportError::InvalidPriceDirective {
+                    path: path.to_path_buf(),
+                    line: idx + 1,
+                    message,
+                }
+            })?;
+            prices.push(price);
+        }
+    }
+    Ok(prices)
+}
+
+/// Parse `DATE [TIME] COMMODITY AMOUNT`, where the amount carries its currency
+/// symbol on either side, e.g. `2023-01-31 PTT 35.25 THB`, `2023/01/31 AAPL $150`
+/// or `2023-01-31 13:45:00 "SET50" 1.234,5 THB`. The time is ignored.
+fn parse_price_directive(directive: &str) -> Result<Price, String> {
+    let directive = directive
+        .split_once(';')
+        .map_or(directive, |(d, _)| d)
+        .trim();
+    let (date, rest) = directive
+        .split_once(char::is_whitespace)
+        .ok_or("expected a date, a commodity and a price")?;
+    let date: Date = date.parse().map_err(|e| format!("{e}"))?;
+    let rest = rest.trim_start();
+    let rest = match rest.split_once(char::is_whitespace) {
+        Some((time, after)) if is_time(time) => after.trim_start(),
+        _ => rest,
+    };
+    let (commodity, amount) = split_symbol(rest)?;
+    let amount = amount.trim();
+    // a quoted symbol may hold digits, it is split off before looking for the number
+    let (quoted, amount) = match amount.starts_with('"') {
+        true => split_symbol(amount).map(|(symbol, rest)| (Some(symbol), rest.trim_start()))?,
+        false => (None, amount),
+    };
+    let start = amount
+        .find(|c: char| c.is_ascii_digit() || c == '-' || c == '+')
+        .ok_or_else(|| format!("missing price in `{amount}`"))?;
+    // digit groups may also be separated by single spaces
+    let number_len = amount[start..]
+        .char_indices()
+        .take_while(|&(idx, c)| {
+            c.is_ascii_digit()
+                || matches!(c, '.' | ',' | '-' | '+')
+                || (c == ' ' && amount[start + idx + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .count();
+    let end = start + number_len;
+    let price = parse_number(&amount[start..end])?;
+    let before = match (quoted, amount[..start].trim()) {
+        (Some(symbol), "") => symbol,
+        (None, symbol) => symbol.to_string(),
+        (Some(_), _) => return Err(format!("ambiguous currency in `{amount}`")),
+    };
+    let currency = match (before.as_str(), amount[end..].trim()) {
+        ("", "") => return Err(format!("missing currency in `{amount}`")),
+        (symbol, "") | ("", symbol) => symbol.trim_matches('"'),
+        _ => return Err(format!("ambiguous currency in `{amount}`")),
+    };
+    Ok(Price {
+        date,
+        commodity: Commodity(commodity),
+        price: price.into(),
+        currency: Commodity(currency.to_string()),
+    })
+}
+
+/// `HH:MM` or `HH:MM:SS`, as hledger allows after the date of a `P` directive.
+fn is_time(token: &str) -> bool {
+    ["%H:%M:%S", "%H:%M"]
+        .iter()
+        .any(|format| chrono::NaiveTime::parse_from_str(token, format).is_ok())
+}
+
+/// Read a number the way hledger does: with both `.` and `,` present the last
+/// one is the decimal mark, a mark appearing more than once groups digits, and
+/// a lone mark is a decimal mark. Spaces only ever group digits.
+fn parse_number(number: &str) -> Result<Decimal, String> {
+    let digits: String = number.chars().filter(|c| *c != ' ').collect();
+    let decimal_mark = match (digits.rfind('.'), digits.rfind(',')) {
+        (Some(period), Some(comma)) => Some(if period > comma { '.' } else { ',' }),
+        (Some(_), None) => (digits.matches('.').count() == 1).then_some('.'),
+        (None, Some(_)) => (digits.matches(',').count() == 1).then_some(','),
+        (None, None) => None,
+    };
+    if let Some(mark) = decimal_mark {
+        if digits.matches(mark).count() > 1 {
+            return Err(format!(
+                "unsupported number `{number}`, decimal mark `{mark}` repeats"
+            ));
+        }
+    }
+    let normalized: String = digits
+        .chars()
+        .filter_map(|c| match c {
+            '.' | ',' if Some(c) == decimal_mark => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect();
+    normalized
+        .parse()
+        .map_err(|e| format!("invalid price `{number}`: {e}"))
+}
+
+/// Split off a leading commodity symbol, which may be double quoted.
+fn split_symbol(s: &str) -> Result<(String, &str), String> {
+    if let Some(quoted) = s.strip_prefix('"') {
+        let (symbol, rest) = quoted
+            .split_once('"')
+            .ok_or("unterminated quoted commodity")?;
+        Ok((symbol.to_string(), rest))
+    } else {
+        let (symbol, rest) = s
+            .split_once(char::is_whitespace)
+            .ok_or("expected a commodity and a price")?;
+        Ok((symbol.to_string(), rest))
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    /// files already loaded, a file reached twice is only loaded once
+    loaded: HashSet<PathBuf>,
+    /// chain of files currently being loaded, used to detect include cycles
+    stack: Vec<PathBuf>,
+    resources: Vec<Resource>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Loader {
+    /// Parse every `---` document of the file. A broken document does not stop
+    /// the others from being parsed, nor a broken part of a document the other
+    /// parts, so that all of them can be reported at once.
+    fn load_file(&mut self, path: &Path) -> Result<(), ImportError> {
+        let canonical = path.canonicalize().map_err(|source| ImportError::FileIO {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if self.stack.contains(&canonical) {
+            let mut cycle = self.stack.clone();
+            cycle.push(canonical);
+            return Err(ImportError::IncludeCycle(cycle));
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let yaml = std::fs::read_to_string(path).map_err(|source| ImportError::FileIO {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.stack.push(canonical);
+        for (idx, doc) in serde_yaml::Deserializer::from_str(&yaml).enumerate() {
+            match Resource::deserialize(doc) {
+                Ok(Resource::Include(include)) => {
+                    let base = path.parent().unwrap_or_else(|| Path::new(""));
+                    for included in expand_input(&base.join(&include.path))? {
+                        self.load_file(&included)?;
+                    }
+                }
+                Ok(r) => self.resources.push(r),
+                Err(e) => {
+                    let errors = part_errors(&yaml, idx)
+                        .filter(|errors| !errors.is_empty())
+                        .unwrap_or_else(|| vec![e]);
+                    self.diagnostics.extend(
+                        errors
+                            .into_iter()
+                            .map(|e| Diagnostic::new(path, &yaml, idx + 1, e)),
+                    );
+                }
+            }
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// Errors of every part of the document at `idx` that failed to parse, or
+/// `None` when its `kind` is unknown. Each part of the `spec` is parsed on its
+/// own with the others skipped, so that one broken part does not hide another,
+/// and with the kind known up front the `spec` keeps its field path even when
+/// it comes before `kind`.
+fn part_errors(yaml: &str, idx: usize) -> Option<Vec<serde_yaml::Error>> {
+    let doc = serde_yaml::Deserializer::from_str(yaml).nth(idx)?;
+    let value = serde_yaml::Value::deserialize(doc).ok()?;
+    let errors = match value.get("kind")?.as_str()? {
+        "CashBalancePortfolio" => spec_errors::<CashBalancePortfolio>(yaml, idx),
+        "Deposit" => scoped_errors::<Deposit>(yaml, idx),
+        "Withdraw" => scoped_errors::<Withdraw>(yaml, idx),
+        "Buy" => scoped_errors::<Buy>(yaml, idx),
+        "Sell" => scoped_errors::<Sell>(yaml, idx),
+        "Interest" => scoped_errors::<InterestPayment>(yaml, idx),
+        "Dividend" => scoped_errors::<Dividend>(yaml, idx),
+        "Split" => scoped_errors::<Split>(yaml, idx),
+        "StockDividend" => scoped_errors::<StockDividend>(yaml, idx),
+        "FxConversion" => scoped_errors::<FxConversion>(yaml, idx),
+        "TransferIn" => scoped_errors::<TransferIn>(yaml, idx),
+        "TransferOut" => scoped_errors::<TransferOut>(yaml, idx),
+        "PortfolioTransfer" => spec_errors::<PortfolioTransfer>(yaml, idx),
+        "Price" => spec_errors::<Price>(yaml, idx),
+        "Include" => spec_errors::<Include>(yaml, idx),
+        _ => return None,
+    };
+    Some(errors)
+}
+
+fn spec_errors<T: DeserializeOwned>(yaml: &str, idx: usize) -> Vec<serde_yaml::Error> {
+    part_error::<T>(yaml, idx, &["spec"]).into_iter().collect()
+}
+
+fn scoped_errors<T: DeserializeOwned>(yaml: &str, idx: usize) -> Vec<serde_yaml::Error> {
+    [
+        part_error::<PortId>(yaml, idx, &["spec", "port_id"]),
+        part_error::<T>(yaml, idx, &["spec", "detail"]),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
 }
 
-pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Resource>, ImportError> {
-    let yaml = std::fs::read_to_string(path.as_ref())?;
-    let mut resources = Vec::new();
-    for doc in serde_yaml::Deserializer::from_str(&yaml) {
-        let value = Value::deserialize(doc).map_err(|e| ImportError::ParseError(e.to_string()))?;
-        let r: Resource =
-            serde_yaml::from_value(value).map_err(|e| ImportError::ParseError(e.to_string()))?;
-        resources.push(r)
+/// Parse the value at `path` of the document at `idx` as `T`, skipping the rest.
+fn part_error<T: DeserializeOwned>(
+    yaml: &str,
+    idx: usize,
+    path: &'static [&'static str],
+) -> Option<serde_yaml::Error> {
+    let doc = serde_yaml::Deserializer::from_str(yaml).nth(idx)?;
+    Part::<T> {
+        path,
+        part: PhantomData,
     }
-    Ok(resources)
+    .deserialize(doc)
+    .err()
+}
+
+/// Seed reaching down the mappings along `path` to the value parsed as `T`.
+struct Part<T> {
+    path: &'static [&'static str],
+    part: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned> DeserializeSeed<'de> for Part<T> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        match self.path {
+            [] => T::deserialize(deserializer).map(|_| ()),
+            _ => deserializer.deserialize_map(self),
+        }
+    }
+}
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for Part<T> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a mapping with `{}`", self.path[0])
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let (key, rest) = self.path.split_first().expect("path is not empty");
+        let mut found = false;
+        while let Some(k) = map.next_key::<String>()? {
+            if k == *key && !found {
+                found = true;
+                map.next_value_seed(Part::<T> {
+                    path: rest,
+                    part: PhantomData,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        match found {
+            true => Ok(()),
+            false => Err(de::Error::missing_field(key)),
+        }
+    }
+}
+
+fn expand_input(input: &Path) -> Result<Vec<PathBuf>, ImportError> {
+    let input_str = input.to_string_lossy();
+    let mut paths = if input_str.contains(['*', '?', '[']) {
+        glob::glob(&input_str)
+            .map_err(|source| ImportError::InvalidPattern {
+                pattern: input_str.to_string(),
+                source,
+            })?
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .collect()
+    } else if input.is_dir() {
+        let mut paths = Vec::new();
+        collect_resource_files(input, &mut paths)?;
+        paths
+    } else {
+        return Ok(vec![input.to_path_buf()]);
+    };
+    if paths.is_empty() {
+        return Err(ImportError::NoMatch(input.to_path_buf()));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn collect_resource_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), ImportError> {
+    let entries = std::fs::read_dir(dir).map_err(|source| ImportError::FileIO {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|source| ImportError::FileIO {
+                path: dir.to_path_buf(),
+                source,
+            })?
+            .path();
+        if path.is_dir() {
+            collect_resource_files(&path, paths)?;
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yaml") | Some("yml")
+        ) {
+            paths.push(path);
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +495,22 @@ pub enum Resource {
     Buy(PortfolioScopedResource<Buy>),
     Sell(PortfolioScopedResource<Sell>),
     Interest(PortfolioScopedResource<InterestPayment>),
+    Dividend(PortfolioScopedResource<Dividend>),
+    Split(PortfolioScopedResource<Split>),
+    StockDividend(PortfolioScopedResource<StockDividend>),
+    FxConversion(PortfolioScopedResource<FxConversion>),
+    TransferIn(PortfolioScopedResource<TransferIn>),
+    TransferOut(PortfolioScopedResource<TransferOut>),
+    PortfolioTransfer(PortfolioTransfer),
+    Price(Price),
+    Include(Include),
+}
+
+/// Pull in resources of other files. `path` is relative to the including file
+/// and may be a directory or a glob pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Include {
+    pub path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,3 +518,324 @@ pub struct PortfolioScopedResource<T> {
     pub port_id: PortId,
     pub detail: T,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(directive: &str) -> (String, Decimal, String) {
+        let price = parse_price_directive(directive).unwrap();
+        assert_eq!(price.date, "2023-01-31".parse().unwrap());
+        (price.commodity.0, price.price.0, price.currency.0)
+    }
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    /// A fresh directory under the system temp dir, named after the test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hledger-fin-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `content` to `path`, creating its directory.
+    fn write(path: &Path, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// A resource file holding the price of `commodity`, to tell loaded files apart.
+    fn price_file(commodity: &str) -> String {
+        format!(
+            "kind: Price\nspec: {{date: 2023-01-02, commodity: {commodity}, price: 1, currency: THB}}\n"
+        )
+    }
+
+    fn include(path: &str) -> String {
+        format!("kind: Include\nspec: {{path: {path}}}\n")
+    }
+
+    /// Commodities of the loaded prices, in load order.
+    fn loaded<P: AsRef<Path>>(inputs: &[P]) -> Vec<String> {
+        from_files(inputs)
+            .unwrap()
+            .into_iter()
+            .filter_map(|r| match r {
+                Resource::Price(p) => Some(p.commodity.0),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn include_cycle_is_reported_with_its_chain() {
+        let dir = temp_dir("include-cycle");
+        let a = dir.join("a.yaml");
+        let b = dir.join("b.yaml");
+        write(
+            &a,
+            &format!("{}---\n{}", price_file("A"), include("b.yaml")),
+        );
+        write(&b, &include("a.yaml"));
+
+        let error = from_files(&[&a]).unwrap_err();
+
+        let a = a.canonicalize().unwrap();
+        let b = b.canonicalize().unwrap();
+        assert!(
+            matches!(&error, ImportError::IncludeCycle(chain) if chain == &[a.clone(), b, a]),
+            "{error}"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn nested_includes_are_relative_to_the_including_file() {
+        let dir = temp_dir("include-nested");
+        let main = dir.join("main.yaml");
+        write(
+            &main,
+            &format!("{}---\n{}", include("sub/first.yaml"), price_file("MAIN")),
+        );
+        write(
+            &dir.join("sub/first.yaml"),
+            &format!(
+                "{}---\n{}",
+                price_file("FIRST"),
+                include("../other/second.yaml")
+            ),
+        );
+        write(&dir.join("other/second.yaml"), &price_file("SECOND"));
+
+        assert_eq!(loaded(&[main]), ["FIRST", "SECOND", "MAIN"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn glob_matches_load_in_path_order() {
+        let dir = temp_dir("glob");
+        for name in ["b", "c", "a"] {
+            write(
+                &dir.join(format!("{name}.yaml")),
+                &price_file(&name.to_uppercase()),
+            );
+        }
+        write(&dir.join("z.yml"), &price_file("Z"));
+
+        assert_eq!(loaded(&[dir.join("*.yaml")]), ["A", "B", "C"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directory_expands_to_resource_files_below_it() {
+        let dir = temp_dir("directory");
+        write(&dir.join("b.yaml"), &price_file("B"));
+        write(&dir.join("a/nested.yml"), &price_file("NESTED"));
+        write(&dir.join("notes.txt"), "not a resource");
+
+        assert_eq!(loaded(&[&dir]), ["NESTED", "B"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn inputs_matching_nothing_are_errors() {
+        let dir = temp_dir("no-match");
+        write(&dir.join("notes.txt"), "not a resource");
+        let pattern = dir.join("*.yaml");
+
+        for input in [&pattern, &dir] {
+            let error = from_files(&[input]).unwrap_err();
+            assert!(
+                matches!(&error, ImportError::NoMatch(path) if path == input),
+                "{error}"
+            );
+        }
+
+        write(&dir.join("main.yaml"), &include("missing/*.yaml"));
+        let error = from_files(&[dir.join("main.yaml")]).unwrap_err();
+        assert!(
+            matches!(&error, ImportError::NoMatch(path) if path == &dir.join("missing/*.yaml")),
+            "{error}"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn price_directive_with_symbol_on_either_side() {
+        assert_eq!(
+            price("2023-01-31 PTT 35.25 THB"),
+            ("PTT".into(), dec("35.25"), "THB".into())
+        );
+        assert_eq!(
+            price("2023/01/31 \"SET 50\" $150 ; comment"),
+            ("SET 50".into(), dec("150"), "$".into())
+        );
+    }
+
+    #[test]
+    fn price_directive_with_quoted_currency_holding_digits() {
+        assert_eq!(
+            price("2023-01-31 PTT \"AB1\" 100"),
+            ("PTT".into(), dec("100"), "AB1".into())
+        );
+        assert_eq!(
+            price("2023-01-31 PTT 100 \"AB1\""),
+            ("PTT".into(), dec("100"), "AB1".into())
+        );
+    }
+
+    #[test]
+    fn price_directives_separated_by_any_whitespace_are_read() {
+        let dir = temp_dir("price-whitespace");
+        let path = dir.join("prices.journal");
+        write(
+            &path,
+            "P 2023-01-31 A 1 THB\nP\t2023-01-31 B 2 THB\nP   2023-01-31\tC\t3 THB\nPX 2023-01-31 D 4 THB\n",
+        );
+
+        let prices = prices_from_hledger(&[&path]).unwrap();
+
+        let read: Vec<(&str, Decimal)> = prices
+            .iter()
+            .map(|p| (&p.commodity.0[..], p.price.0))
+            .collect();
+        assert_eq!(
+            read,
+            vec![("A", dec("1")), ("B", dec("2")), ("C", dec("3"))]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn price_directive_time_is_skipped() {
+        assert_eq!(
+            price("2023-01-31 13:45 PTT 35.25 THB"),
+            ("PTT".into(), dec("35.25"), "THB".into())
+        );
+        assert_eq!(
+            price("2023-01-31 13:45:10 PTT 35.25 THB"),
+            ("PTT".into(), dec("35.25"), "THB".into())
+        );
+    }
+
+    #[test]
+    fn price_directive_decimal_and_digit_group_marks() {
+        let amount = |directive: &str| price(directive).1;
+        assert_eq!(amount("2023-01-31 X 1,234.5 THB"), dec("1234.5"));
+        assert_eq!(amount("2023-01-31 X 1.234,5 EUR"), dec("1234.5"));
+        assert_eq!(amount("2023-01-31 X 35,25 EUR"), dec("35.25"));
+        assert_eq!(amount("2023-01-31 X 1.234.567 IDR"), dec("1234567"));
+        assert_eq!(amount("2023-01-31 X 1 234 567,5 EUR"), dec("1234567.5"));
+    }
+
+    #[test]
+    fn price_directive_with_repeated_decimal_mark_is_unsupported() {
+        let error = parse_price_directive("2023-01-31 X 1,234.5.6 THB").unwrap_err();
+        assert!(error.contains("unsupported number"), "{error}");
+    }
+
+    /// Diagnostics of loading `yaml` from a file of its own.
+    fn diagnostics(name: &str, yaml: &str) -> Vec<Diagnostic> {
+        let dir = temp_dir(name);
+        let path = dir.join("resources.yaml");
+        write(&path, yaml);
+        let error = from_files(&[&path]).unwrap_err();
+        std::fs::remove_dir_all(dir).unwrap();
+        match error {
+            ImportError::ParseError(diagnostics) => diagnostics,
+            e => panic!("expected a parse error, got {e}"),
+        }
+    }
+
+    /// Message, document, line and column of a diagnostic.
+    fn located(d: &Diagnostic) -> (&str, usize, Option<usize>, Option<usize>) {
+        (&d.message, d.document, d.line, d.column)
+    }
+
+    #[test]
+    fn unknown_kind_points_at_the_kind() {
+        let yaml = "kind: Bogus\nspec: {}\n";
+
+        let diagnostics = diagnostics("unknown-kind", yaml);
+
+        assert_eq!(diagnostics.len(), 1);
+        let d = &diagnostics[0];
+        assert!(
+            d.message.starts_with("kind: unknown variant `Bogus`"),
+            "{d}"
+        );
+        assert_eq!((d.document, d.line, d.column), (1, Some(1), Some(7)));
+        assert_eq!(d.source_line.as_deref(), Some("kind: Bogus"));
+    }
+
+    #[test]
+    fn every_broken_part_of_every_document_is_reported() {
+        let yaml = r#"kind: Deposit
+spec:
+  port_id: port
+  detail: {date: 2023-01-02, amount: 100}
+---
+kind: Buy
+spec:
+  detail:
+    date: 2023-01-02
+    commodity: PTT
+    price: abc
+    volume: 1
+---
+kind: Price
+spec: {date: 2023-01-02, commodity: PTT, price: 1.5x, currency: THB}
+"#;
+
+        let diagnostics = diagnostics("broken-parts", yaml);
+
+        let decimal = "expected a Decimal type representing a fixed-point number";
+        assert_eq!(
+            diagnostics.iter().map(located).collect::<Vec<_>>(),
+            vec![
+                ("spec: missing field `port_id`", 2, Some(8), Some(3)),
+                (
+                    &format!("spec.detail.price: invalid value: string \"abc\", {decimal}")[..],
+                    2,
+                    Some(11),
+                    Some(12)
+                ),
+                (
+                    &format!("spec.price: invalid value: string \"1.5x\", {decimal}")[..],
+                    3,
+                    Some(15),
+                    Some(49)
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].to_string(),
+            format!(
+                "error: spec.detail.price: invalid value: string \"abc\", {decimal}\n  --> {}:11:12 (document 2)\n   |\n11 |     price: abc\n   |            ^",
+                diagnostics[1].path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn spec_before_kind_keeps_its_field_path() {
+        let yaml = r#"spec:
+  port_id: port
+  detail:
+    date: 2023-01-02
+    commodity: PTT
+    volume: 1
+kind: Buy
+"#;
+
+        let diagnostics = diagnostics("spec-first", yaml);
+
+        assert_eq!(
+            diagnostics.iter().map(located).collect::<Vec<_>>(),
+            vec![("spec.detail: missing field `price`", 1, Some(4), Some(5))]
+        );
+    }
+}
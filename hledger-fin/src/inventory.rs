@@ -1,18 +1,117 @@
-use crate::model::{CommodityAmount, CommodityPrice, Date};
+use crate::model::{
+    port::CostBasis, txn::LotSelection, Commodity, CommodityAmount, CommodityPrice, Date, LotId,
+};
 use rust_decimal::Decimal;
+use serde::Serialize;
 use std::cmp;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Lot {
     pub date: Date,
     pub price: CommodityPrice,
     pub volume: CommodityAmount,
+    pub currency: Commodity,
+    pub id: Option<LotId>,
+    /// Total cost of the lot. It is `volume * price` until a split or a partial
+    /// sale leaves a unit price that `Decimal` cannot hold exactly, from then on
+    /// this is what the lot is worth at cost.
+    pub cost: CommodityAmount,
 }
 
+impl Lot {
+    /// Take `volume` out of the lot. The part taken carries its share of the cost
+    /// and the lot keeps the remainder, so the two add back up exactly.
+    fn take(&mut self, volume: Decimal) -> Lot {
+        let cost = if volume == self.volume.0 {
+            self.cost.0
+        } else {
+            self.cost.0 * volume / self.volume.0
+        };
+        self.volume.0 -= volume;
+        self.cost.0 -= cost;
+        Lot {
+            volume: CommodityAmount(volume),
+            cost: CommodityAmount(cost),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum InventoryError {
+    #[error("requested volume {requested} but only {available} is available")]
+    InsufficientVolume {
+        requested: Decimal,
+        available: Decimal,
+    },
+    #[error("requested volume {requested} from lot {} but only {available} is available", .lot_id.0)]
+    InsufficientLotVolume {
+        lot_id: LotId,
+        requested: Decimal,
+        available: Decimal,
+    },
+    #[error("cost basis does not support lot selection")]
+    LotSelectionUnsupported,
+}
+
+/// Taking lots out is all-or-nothing: on error the inventory is left untouched.
 pub trait Inventory {
     fn push(&mut self, lot: Lot);
-    fn pop(&mut self, volume: &CommodityAmount) -> Vec<Lot>;
+    fn pop(&mut self, volume: &CommodityAmount) -> Result<Vec<Lot>, InventoryError>;
     fn inventory(&self) -> &Vec<Lot>;
+
+    /// Turn every `from` units of each lot into `to` units, keeping the lot cost.
+    fn split(&mut self, from: Decimal, to: Decimal);
+
+    /// Take out exactly the selected lots instead of letting the cost basis decide.
+    fn pop_lots(&mut self, _selections: &[LotSelection]) -> Result<Vec<Lot>, InventoryError> {
+        Err(InventoryError::LotSelectionUnsupported)
+    }
+}
+
+pub fn new_inventory(cost_basis: &CostBasis) -> Box<dyn Inventory> {
+    match cost_basis {
+        CostBasis::Fifo => Box::<FifoInventory>::default(),
+        CostBasis::Lifo => Box::<LifoInventory>::default(),
+        CostBasis::Average => Box::<AverageCostInventory>::default(),
+        CostBasis::Specific => Box::<SpecificInventory>::default(),
+    }
+}
+
+/// Volume is scaled by `to / from` and the unit price derived back from the lot
+/// cost, which is kept as is so that the total survives ratios such as 1:3.
+fn split_lots(lots: &mut [Lot], from: Decimal, to: Decimal) {
+    for lot in lots.iter_mut().filter(|lot| lot.volume.0 != Decimal::ZERO) {
+        let volume = lot.volume.0 * to / from;
+        lot.price = CommodityPrice(lot.cost.0 / volume);
+        lot.volume = CommodityAmount(volume);
+    }
+}
+
+/// Take `volume` out of `lots` in the order given. Works on the lots in place,
+/// so callers pass a copy to keep their inventory untouched on error.
+fn take_in_order<'a>(
+    lots: impl Iterator<Item = &'a mut Lot>,
+    volume: &CommodityAmount,
+) -> Result<Vec<Lot>, InventoryError> {
+    let mut used_lots = Vec::new();
+    let mut remaining_volume = volume.0;
+    for lot in lots {
+        if remaining_volume <= Decimal::ZERO {
+            break;
+        }
+        let volume_taken = cmp::min(remaining_volume, lot.volume.0);
+        remaining_volume -= volume_taken;
+        used_lots.push(lot.take(volume_taken));
+    }
+
+    if remaining_volume > Decimal::ZERO {
+        return Err(InventoryError::InsufficientVolume {
+            requested: volume.0,
+            available: volume.0 - remaining_volume,
+        });
+    }
+    Ok(used_lots)
 }
 
 #[derive(Debug, Clone, Default)]
@@ -25,51 +124,487 @@ impl Inventory for FifoInventory {
         self.inventory_inner.push(lot);
     }
 
-    fn pop(&mut self, volume: &CommodityAmount) -> Vec<Lot> {
-        let mut used_lots = Vec::new();
-        let mut remaining_volume = volume.0;
-        for lot in self.inventory() {
-            if remaining_volume <= Decimal::ZERO {
-                break;
-            } else {
-                let volume_taken = cmp::min(remaining_volume, lot.volume.0);
-                remaining_volume -= volume_taken;
-                let used_lot = Lot {
-                    volume: CommodityAmount(volume_taken),
-                    ..lot.clone()
+    fn pop(&mut self, volume: &CommodityAmount) -> Result<Vec<Lot>, InventoryError> {
+        let mut remaining_lots = self.inventory_inner.clone();
+        let used_lots = take_in_order(remaining_lots.iter_mut(), volume)?;
+        self.inventory_inner = remaining_lots
+            .into_iter()
+            .filter(|i| i.volume.0 > Decimal::ZERO)
+            .collect();
+
+        Ok(used_lots)
+    }
+
+    fn inventory(&self) -> &Vec<Lot> {
+        &self.inventory_inner
+    }
+
+    fn split(&mut self, from: Decimal, to: Decimal) {
+        split_lots(&mut self.inventory_inner, from, to);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LifoInventory {
+    inventory_inner: Vec<Lot>,
+}
+
+impl Inventory for LifoInventory {
+    fn push(&mut self, lot: Lot) {
+        self.inventory_inner.push(lot);
+    }
+
+    fn pop(&mut self, volume: &CommodityAmount) -> Result<Vec<Lot>, InventoryError> {
+        let mut remaining_lots = self.inventory_inner.clone();
+        let used_lots = take_in_order(remaining_lots.iter_mut().rev(), volume)?;
+        self.inventory_inner = remaining_lots
+            .into_iter()
+            .filter(|i| i.volume.0 > Decimal::ZERO)
+            .collect();
+
+        Ok(used_lots)
+    }
+
+    fn inventory(&self) -> &Vec<Lot> {
+        &self.inventory_inner
+    }
+
+    fn split(&mut self, from: Decimal, to: Decimal) {
+        split_lots(&mut self.inventory_inner, from, to);
+    }
+}
+
+/// Keeps at most one lot whose price is the moving weighted-average cost
+/// of everything pushed so far. Selling does not change the average.
+#[derive(Debug, Clone, Default)]
+pub struct AverageCostInventory {
+    inventory_inner: Vec<Lot>,
+}
+
+impl Inventory for AverageCostInventory {
+    fn push(&mut self, lot: Lot) {
+        match self.inventory_inner.pop() {
+            None => self.inventory_inner.push(lot),
+            Some(held) => {
+                let total_volume = held.volume.0 + lot.volume.0;
+                let total_cost = held.cost.0 + lot.cost.0;
+                let avg_price = if total_volume == Decimal::ZERO {
+                    Decimal::ZERO
+                } else {
+                    total_cost / total_volume
                 };
-                used_lots.push(used_lot);
+                self.inventory_inner.push(Lot {
+                    date: lot.date,
+                    price: CommodityPrice(avg_price),
+                    volume: CommodityAmount(total_volume),
+                    currency: lot.currency,
+                    id: None,
+                    cost: CommodityAmount(total_cost),
+                })
             }
         }
+    }
 
-        // TODO: properly handle error
-        if remaining_volume > Decimal::ZERO {
-            panic!("not enough volume to take out from inventory")
+    fn pop(&mut self, volume: &CommodityAmount) -> Result<Vec<Lot>, InventoryError> {
+        if volume.0 <= Decimal::ZERO {
+            return Ok(Vec::new());
         }
 
-        self.inventory_inner = self
+        let held_volume = self
             .inventory_inner
-            .iter()
-            .enumerate()
-            .map(|(idx, original_lot)| {
-                // TODO: avoid cloning, add performance benchmarking
-                if idx >= used_lots.len() {
-                    original_lot.clone()
-                } else {
-                    let used_lot = &used_lots[idx];
-                    Lot {
-                        volume: &original_lot.volume - &used_lot.volume,
-                        ..original_lot.clone()
-                    }
+            .first()
+            .map(|lot| lot.volume.0)
+            .unwrap_or_default();
+
+        if volume.0 > held_volume {
+            return Err(InventoryError::InsufficientVolume {
+                requested: volume.0,
+                available: held_volume,
+            });
+        }
+
+        let mut held = self.inventory_inner.remove(0);
+        let used_lot = held.take(volume.0);
+        if held.volume.0 > Decimal::ZERO {
+            self.inventory_inner.push(held);
+        }
+
+        Ok(vec![used_lot])
+    }
+
+    fn inventory(&self) -> &Vec<Lot> {
+        &self.inventory_inner
+    }
+
+    fn split(&mut self, from: Decimal, to: Decimal) {
+        split_lots(&mut self.inventory_inner, from, to);
+    }
+}
+
+/// Lots are taken out by their id when a sell names them, falling back to
+/// FIFO order when it does not.
+#[derive(Debug, Clone, Default)]
+pub struct SpecificInventory {
+    fifo: FifoInventory,
+}
+
+impl Inventory for SpecificInventory {
+    fn push(&mut self, lot: Lot) {
+        self.fifo.push(lot);
+    }
+
+    fn pop(&mut self, volume: &CommodityAmount) -> Result<Vec<Lot>, InventoryError> {
+        self.fifo.pop(volume)
+    }
+
+    fn inventory(&self) -> &Vec<Lot> {
+        self.fifo.inventory()
+    }
+
+    fn split(&mut self, from: Decimal, to: Decimal) {
+        self.fifo.split(from, to);
+    }
+
+    fn pop_lots(&mut self, selections: &[LotSelection]) -> Result<Vec<Lot>, InventoryError> {
+        // work on a copy so that a failing selection leaves the inventory untouched
+        let mut remaining_lots = self.fifo.inventory_inner.clone();
+        let mut used_lots = Vec::new();
+        for selection in selections {
+            let mut remaining_volume = selection.volume.0;
+            for lot in remaining_lots
+                .iter_mut()
+                .filter(|lot| lot.id.as_ref() == Some(&selection.lot_id))
+            {
+                if remaining_volume <= Decimal::ZERO {
+                    break;
                 }
-            })
+                let volume_taken = cmp::min(remaining_volume, lot.volume.0);
+                remaining_volume -= volume_taken;
+                used_lots.push(lot.take(volume_taken));
+            }
+
+            if remaining_volume > Decimal::ZERO {
+                return Err(InventoryError::InsufficientLotVolume {
+                    lot_id: selection.lot_id.clone(),
+                    requested: selection.volume.0,
+                    available: selection.volume.0 - remaining_volume,
+                });
+            }
+        }
+
+        self.fifo.inventory_inner = remaining_lots
+            .into_iter()
             .filter(|i| i.volume.0 > Decimal::ZERO)
             .collect();
 
-        used_lots
+        Ok(used_lots)
     }
+}
 
-    fn inventory(&self) -> &Vec<Lot> {
-        &self.inventory_inner
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lot(date: &str, price: i64, volume: i64) -> Lot {
+        Lot {
+            date: date.parse().unwrap(),
+            price: CommodityPrice(price.into()),
+            volume: CommodityAmount(volume.into()),
+            currency: Commodity("THB".into()),
+            id: None,
+            cost: CommodityAmount((price * volume).into()),
+        }
+    }
+
+    fn with_id(lot: Lot, id: &str) -> Lot {
+        Lot {
+            id: Some(LotId(id.into())),
+            ..lot
+        }
+    }
+
+    fn amount(volume: i64) -> CommodityAmount {
+        CommodityAmount(volume.into())
+    }
+
+    fn select(id: &str, volume: i64) -> LotSelection {
+        LotSelection {
+            lot_id: LotId(id.into()),
+            volume: amount(volume),
+        }
+    }
+
+    fn filled(mut inventory: impl Inventory, lots: &[Lot]) -> impl Inventory {
+        for lot in lots {
+            inventory.push(lot.clone());
+        }
+        inventory
+    }
+
+    #[test]
+    fn fifo_pops_oldest_lots_first() {
+        let mut inventory = filled(
+            FifoInventory::default(),
+            &[lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 100)],
+        );
+
+        let used = inventory.pop(&amount(150)).unwrap();
+
+        assert_eq!(
+            used,
+            vec![lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 50)]
+        );
+        assert_eq!(inventory.inventory(), &vec![lot("2023-02-01", 12, 50)]);
+    }
+
+    #[test]
+    fn fifo_keeps_the_rest_of_a_partly_taken_lot() {
+        let mut inventory = filled(FifoInventory::default(), &[lot("2023-01-01", 10, 100)]);
+
+        let used = inventory.pop(&amount(30)).unwrap();
+
+        assert_eq!(used, vec![lot("2023-01-01", 10, 30)]);
+        assert_eq!(inventory.inventory(), &vec![lot("2023-01-01", 10, 70)]);
+    }
+
+    #[test]
+    fn fifo_oversell_leaves_inventory_untouched() {
+        let lots = [lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 100)];
+        let mut inventory = filled(FifoInventory::default(), &lots);
+
+        let error = inventory.pop(&amount(250)).unwrap_err();
+
+        assert_eq!(
+            error,
+            InventoryError::InsufficientVolume {
+                requested: 250.into(),
+                available: 200.into(),
+            }
+        );
+        assert_eq!(inventory.inventory(), &lots.to_vec());
+    }
+
+    #[test]
+    fn fifo_does_not_support_lot_selection() {
+        let mut inventory = filled(
+            FifoInventory::default(),
+            &[with_id(lot("2023-01-01", 10, 100), "a")],
+        );
+
+        let error = inventory.pop_lots(&[select("a", 10)]).unwrap_err();
+
+        assert_eq!(error, InventoryError::LotSelectionUnsupported);
+    }
+
+    #[test]
+    fn lifo_pops_newest_lots_first() {
+        let mut inventory = filled(
+            LifoInventory::default(),
+            &[lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 100)],
+        );
+
+        let used = inventory.pop(&amount(150)).unwrap();
+
+        assert_eq!(
+            used,
+            vec![lot("2023-02-01", 12, 100), lot("2023-01-01", 10, 50)]
+        );
+        assert_eq!(inventory.inventory(), &vec![lot("2023-01-01", 10, 50)]);
+    }
+
+    #[test]
+    fn lifo_keeps_the_rest_of_a_partly_taken_lot() {
+        let mut inventory = filled(
+            LifoInventory::default(),
+            &[lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 100)],
+        );
+
+        let used = inventory.pop(&amount(30)).unwrap();
+
+        assert_eq!(used, vec![lot("2023-02-01", 12, 30)]);
+        assert_eq!(
+            inventory.inventory(),
+            &vec![lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 70)]
+        );
+    }
+
+    #[test]
+    fn lifo_oversell_leaves_inventory_untouched() {
+        let lots = [lot("2023-01-01", 10, 100), lot("2023-02-01", 12, 100)];
+        let mut inventory = filled(LifoInventory::default(), &lots);
+
+        assert!(inventory.pop(&amount(201)).is_err());
+        assert_eq!(inventory.inventory(), &lots.to_vec());
+    }
+
+    #[test]
+    fn average_collapses_lots_into_a_weighted_average() {
+        let inventory = filled(
+            AverageCostInventory::default(),
+            &[lot("2023-01-01", 10, 100), lot("2023-02-01", 16, 50)],
+        );
+
+        assert_eq!(inventory.inventory(), &vec![lot("2023-02-01", 12, 150)]);
+    }
+
+    #[test]
+    fn average_pop_keeps_the_average_of_the_rest() {
+        let mut inventory = filled(
+            AverageCostInventory::default(),
+            &[lot("2023-01-01", 10, 100), lot("2023-02-01", 16, 50)],
+        );
+
+        let used = inventory.pop(&amount(60)).unwrap();
+
+        assert_eq!(used, vec![lot("2023-02-01", 12, 60)]);
+        assert_eq!(inventory.inventory(), &vec![lot("2023-02-01", 12, 90)]);
+    }
+
+    #[test]
+    fn average_pop_of_everything_empties_the_inventory() {
+        let mut inventory = filled(
+            AverageCostInventory::default(),
+            &[lot("2023-01-01", 10, 100)],
+        );
+
+        let used = inventory.pop(&amount(100)).unwrap();
+
+        assert_eq!(used, vec![lot("2023-01-01", 10, 100)]);
+        assert!(inventory.inventory().is_empty());
+    }
+
+    #[test]
+    fn average_oversell_leaves_inventory_untouched() {
+        let mut inventory = filled(
+            AverageCostInventory::default(),
+            &[lot("2023-01-01", 10, 100)],
+        );
+
+        let error = inventory.pop(&amount(101)).unwrap_err();
+
+        assert_eq!(
+            error,
+            InventoryError::InsufficientVolume {
+                requested: 101.into(),
+                available: 100.into(),
+            }
+        );
+        assert_eq!(inventory.inventory(), &vec![lot("2023-01-01", 10, 100)]);
+    }
+
+    #[test]
+    fn specific_pops_exactly_the_selected_lots() {
+        let mut inventory = filled(
+            SpecificInventory::default(),
+            &[
+                with_id(lot("2023-01-01", 10, 100), "a"),
+                with_id(lot("2023-02-01", 12, 100), "b"),
+                with_id(lot("2023-03-01", 14, 100), "c"),
+            ],
+        );
+
+        let used = inventory
+            .pop_lots(&[select("c", 100), select("a", 40)])
+            .unwrap();
+
+        assert_eq!(
+            used,
+            vec![
+                with_id(lot("2023-03-01", 14, 100), "c"),
+                with_id(lot("2023-01-01", 10, 40), "a"),
+            ]
+        );
+        assert_eq!(
+            inventory.inventory(),
+            &vec![
+                with_id(lot("2023-01-01", 10, 60), "a"),
+                with_id(lot("2023-02-01", 12, 100), "b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn specific_falls_back_to_fifo_without_selection() {
+        let mut inventory = filled(
+            SpecificInventory::default(),
+            &[
+                with_id(lot("2023-01-01", 10, 100), "a"),
+                with_id(lot("2023-02-01", 12, 100), "b"),
+            ],
+        );
+
+        let used = inventory.pop(&amount(120)).unwrap();
+
+        assert_eq!(
+            used,
+            vec![
+                with_id(lot("2023-01-01", 10, 100), "a"),
+                with_id(lot("2023-02-01", 12, 20), "b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn specific_selection_beyond_the_lot_fails_atomically() {
+        let lots = [
+            with_id(lot("2023-01-01", 10, 100), "a"),
+            with_id(lot("2023-02-01", 12, 100), "b"),
+        ];
+        let mut inventory = filled(SpecificInventory::default(), &lots);
+
+        // the first selection is satisfiable, it must not be taken either
+        let error = inventory
+            .pop_lots(&[select("a", 50), select("b", 150)])
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            InventoryError::InsufficientLotVolume {
+                lot_id: LotId("b".into()),
+                requested: 150.into(),
+                available: 100.into(),
+            }
+        );
+        assert_eq!(inventory.inventory(), &lots.to_vec());
+    }
+
+    #[test]
+    fn specific_selection_of_an_unknown_lot_fails() {
+        let mut inventory = filled(
+            SpecificInventory::default(),
+            &[with_id(lot("2023-01-01", 10, 100), "a")],
+        );
+
+        let error = inventory.pop_lots(&[select("z", 10)]).unwrap_err();
+
+        assert_eq!(
+            error,
+            InventoryError::InsufficientLotVolume {
+                lot_id: LotId("z".into()),
+                requested: 10.into(),
+                available: 0.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn split_preserves_total_cost_exactly() {
+        let mut inventory = filled(FifoInventory::default(), &[lot("2023-01-01", 10, 100)]);
+
+        inventory.split(1.into(), 3.into());
+
+        let [split] = &inventory.inventory()[..] else {
+            panic!("expected a single lot");
+        };
+        assert_eq!(split.volume, amount(300));
+        assert_eq!(split.cost, amount(1000));
+
+        // 10 / 3 has no exact decimal form, selling in parts must still add up
+        let sold: Decimal = [100, 100, 100]
+            .iter()
+            .map(|volume| inventory.pop(&amount(*volume)).unwrap()[0].cost.0)
+            .sum();
+        assert_eq!(sold, Decimal::from(1000));
+        assert!(inventory.inventory().is_empty());
     }
 }
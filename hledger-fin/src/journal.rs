@@ -1,27 +1,97 @@
+// journal errors are cold and carry their whole context, boxing them buys nothing
+#![allow(clippy::result_large_err)]
+
 use crate::{
     input::Resource,
-    inventory::{FifoInventory, Inventory, Lot},
+    inventory::{self, Inventory, InventoryError, Lot},
     model::{
-        port::CashBalancePortfolio,
-        txn::{cashbalance as cb, Buy, DatedTransaction, Deposit, InterestPayment, Sell, Withdraw},
-        Account, Commodity, CommodityAmount, Date, PortId,
+        port::{CashBalancePortfolio, CostBasis},
+        price::Price,
+        txn::{
+            cashbalance as cb, Buy, DatedTransaction, Deposit, Dividend, FxConversion,
+            InterestPayment, LotSelection, PortfolioTransfer, Sell, Split, StockDividend,
+            TransferIn, TransferOut, Withdraw,
+        },
+        Account, Commodity, CommodityAmount, CommodityPrice, Date, LotId, PortId,
     },
 };
+use chrono::Datelike;
 use rust_decimal::Decimal;
-use std::collections::{hash_map::Entry, HashMap};
+use serde::{Serialize, Serializer};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    ops::Range,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct Posting {
     pub account: Account,
+    #[serde(serialize_with = "serialize_amount")]
     pub amount: Option<(Commodity, CommodityAmount)>,
+    /// Balance of the amount commodity in the account once this posting is applied
+    pub assertion: Option<CommodityAmount>,
+    /// Lot the amount is bought into or sold out of, at its cost
+    pub cost: Option<LotCost>,
+    /// Profit or loss of a sale that is already implied when the sold lots are
+    /// valued at their sale price instead of their cost
+    pub realized_gain: bool,
     pub comment: Option<String>,
 }
 
+/// Cost basis of a lot, rendered as `{unit} [date] @ unit`. `total` is set when
+/// the unit price does not multiply back to the exact lot cost, in which case
+/// the posting is priced as a whole.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct LotCost {
+    pub currency: Commodity,
+    pub unit: CommodityPrice,
+    pub date: Date,
+    pub id: Option<LotId>,
+    pub total: Option<CommodityAmount>,
+    /// Price the lot is sold at, when the sale proceeds are booked before fees
+    pub sold_at: Option<CommodityPrice>,
+}
+
+impl LotCost {
+    fn new(lot: &Lot) -> Self {
+        Self {
+            currency: lot.currency.clone(),
+            unit: lot.price.clone(),
+            date: lot.date,
+            id: lot.id.clone(),
+            total: (lot.price.0 * lot.volume.0 != lot.cost.0).then(|| lot.cost.clone()),
+            sold_at: None,
+        }
+    }
+}
+
+/// Write an amount as `{"commodity": .., "quantity": ..}` rather than a pair.
+fn serialize_amount<S: Serializer>(
+    amount: &Option<(Commodity, CommodityAmount)>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Amount<'a> {
+        commodity: &'a Commodity,
+        quantity: &'a CommodityAmount,
+    }
+    amount
+        .as_ref()
+        .map(|(commodity, quantity)| Amount {
+            commodity,
+            quantity,
+        })
+        .serialize(serializer)
+}
+
 impl Posting {
     pub fn new(account: impl Into<Account>) -> Self {
         Self {
             account: account.into(),
             amount: None,
+            assertion: None,
+            cost: None,
+            realized_gain: false,
             comment: None,
         }
     }
@@ -36,6 +106,20 @@ impl Posting {
         }
     }
 
+    pub fn with_cost(self, cost: LotCost) -> Self {
+        Self {
+            cost: Some(cost),
+            ..self
+        }
+    }
+
+    pub fn with_realized_gain(self) -> Self {
+        Self {
+            realized_gain: true,
+            ..self
+        }
+    }
+
     pub fn with_comment(self, comment: impl Into<String>) -> Self {
         Self {
             comment: Some(comment.into()),
@@ -44,7 +128,7 @@ impl Posting {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct JournalEntry {
     pub date: Date,
     pub description: String,
@@ -52,72 +136,454 @@ pub struct JournalEntry {
     pub inventory: Option<Vec<Lot>>,
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum JournalError {
+    #[error(
+        "portfolio {}, {} on {}: unable to take {requested} out of inventory, only {available} available",
+        .port_id.0, .commodity.0, .date
+    )]
+    InsufficientVolume {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        requested: Decimal,
+        available: Decimal,
+    },
+    #[error(
+        "portfolio {}, {} on {}: unable to take {requested} out of lot {}, only {available} available",
+        .port_id.0, .commodity.0, .date, .lot_id.0
+    )]
+    InsufficientLotVolume {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        lot_id: LotId,
+        requested: Decimal,
+        available: Decimal,
+    },
+    #[error(
+        "portfolio {}, {} on {}: cost basis {cost_basis:?} does not support lot selection",
+        .port_id.0, .commodity.0, .date
+    )]
+    LotSelectionUnsupported {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        cost_basis: CostBasis,
+    },
+    #[error(
+        "portfolio {}, {} on {}: selected lots total {selected} but sell volume is {requested}",
+        .port_id.0, .commodity.0, .date
+    )]
+    LotSelectionMismatch {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        selected: Decimal,
+        requested: Decimal,
+    },
+    #[error(
+        "portfolio {} on {}: {account} is required but not configured",
+        .port_id.0, .date
+    )]
+    MissingAccount {
+        port_id: PortId,
+        date: Date,
+        account: &'static str,
+    },
+    #[error(
+        "portfolio {}, {} on {}: dividend needs exactly one of per_share or amount",
+        .port_id.0, .commodity.0, .date
+    )]
+    InvalidDividendAmount {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+    },
+    #[error(
+        "portfolio {}, {} on {}: traded in {} but inventory is held in {}",
+        .port_id.0, .commodity.0, .date, .traded.0, .held.0
+    )]
+    CurrencyMismatch {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        held: Commodity,
+        traded: Commodity,
+    },
+    #[error(
+        "portfolio {}, {} on {}: ratio {from}:{to} must be positive",
+        .port_id.0, .commodity.0, .date
+    )]
+    InvalidRatio {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        from: Decimal,
+        to: Decimal,
+    },
+    #[error(
+        "portfolio {}, {} on {}: volume {volume} must be positive",
+        .port_id.0, .commodity.0, .date
+    )]
+    InvalidVolume {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+        volume: Decimal,
+    },
+    #[error(
+        "portfolio {}, {} on {}: transfer in has no lots",
+        .port_id.0, .commodity.0, .date
+    )]
+    EmptyTransferIn {
+        port_id: PortId,
+        commodity: Commodity,
+        date: Date,
+    },
+    #[error("portfolio {} on {}: no such portfolio", .port_id.0, .date)]
+    UnknownPortfolio { port_id: PortId, date: Date },
+    #[error("portfolio {} on {}: cannot transfer to itself", .port_id.0, .date)]
+    SamePortfolioTransfer { port_id: PortId, date: Date },
+    #[error("portfolio {} on {}: transfer has neither cash nor position", .port_id.0, .date)]
+    EmptyTransfer { port_id: PortId, date: Date },
+}
+
 struct CategorizedResources {
     portfolios: Vec<CashBalancePortfolio>,
-    transactions: HashMap<PortId, Vec<cb::Transaction>>,
+    transactions: Vec<Transaction>,
+}
+
+/// A transaction of one portfolio, or one spanning two of them.
+enum Transaction {
+    Portfolio(PortId, cb::Transaction),
+    Transfer(PortfolioTransfer),
+}
+
+impl DatedTransaction for Transaction {
+    fn date(&self) -> &Date {
+        match self {
+            Transaction::Portfolio(_, t) => t.date(),
+            Transaction::Transfer(t) => t.date(),
+        }
+    }
+}
+
+impl Transaction {
+    /// Dividends are replayed ahead of everything else on their ex-date, a lot
+    /// bought on the ex-date is not entitled whatever its place in the input.
+    fn replays_first(&self) -> bool {
+        matches!(
+            self,
+            Transaction::Portfolio(_, cb::Transaction::Dividend(_))
+        )
+    }
+}
+
+/// Outcome of replaying every transaction. A failing transaction produces no
+/// entries but does not stop the others from being replayed.
+pub struct JournalBuild {
+    pub entries: Vec<JournalEntry>,
+    /// Range of `entries` listed under each portfolio
+    pub sections: Vec<(PortId, Range<usize>)>,
+    pub errors: Vec<JournalError>,
+    pub holdings: Vec<Holding>,
+    pub disposals: Vec<Disposal>,
+}
+
+/// Open lots of one commodity in one portfolio once every transaction is replayed.
+pub struct Holding {
+    pub port_id: PortId,
+    pub commodity: Commodity,
+    pub lots: Vec<Lot>,
+}
+
+/// A lot matched against a sale. `gain` is the amount booked to the profit and
+/// loss account for this lot, with the sign flipped, so fees only reduce it when
+/// the portfolio capitalizes them.
+#[derive(Debug, Clone)]
+pub struct Disposal {
+    pub port_id: PortId,
+    pub commodity: Commodity,
+    pub currency: Commodity,
+    pub date: Date,
+    pub lot: Lot,
+    /// The lot pools every buy under the average cost basis, so its date is
+    /// that of the latest buy rather than of the acquisition
+    pub pooled: bool,
+    pub proceeds: Decimal,
+    pub fees: Decimal,
+    pub gain: Decimal,
 }
 
-pub fn build_journal(resources: Vec<Resource>) -> Vec<JournalEntry> {
-    let mut categorized_resources = categorize_resources(resources);
-    let mut result = Vec::new();
+/// Options controlling how transactions are replayed.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Ignore transactions dated after this day.
+    pub as_of: Option<Date>,
+    /// Assert the running balance on every cash and position posting.
+    pub balance_assertions: bool,
+    /// Order the running balances accumulate in, which must be the order the
+    /// backend checks the assertions in.
+    pub assertion_order: AssertionOrder,
+    /// Annotate every position posting with its lot cost instead of balancing
+    /// it through the conversion account, so that the lots a backend books are
+    /// the lots held.
+    pub lot_costs: bool,
+}
+
+/// Order in which a backend checks balance assertions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AssertionOrder {
+    /// By date, then by position in the journal, the way hledger checks them.
+    #[default]
+    Date,
+    /// By position in the journal, the way Ledger checks them.
+    File,
+    /// By year, then by position in the journal: the order Ledger reads a
+    /// journal split into one file per year.
+    FilePerYear,
+}
+
+/// Transactions of all portfolios are replayed together in date order so that
+/// transfers see the inventories of both sides as of their date. Entries are
+/// still grouped by portfolio, with a transfer listed under its source.
+pub fn build(resources: Vec<Resource>, options: &BuildOptions) -> JournalBuild {
+    let categorized_resources = categorize_resources(resources);
+    let mut writers: Vec<CashBalanceJournalWriter> = Vec::new();
+    let mut writer_index: HashMap<PortId, usize> = HashMap::new();
     for port in categorized_resources.portfolios {
-        if let Some(transactions) = categorized_resources.transactions.remove(&port.port_id) {
-            let writer = CashBalanceJournalWriter { port };
-            let entries = writer.to_journal_entries(transactions);
-            result.extend(entries);
+        if let Entry::Vacant(e) = writer_index.entry(port.port_id.clone()) {
+            e.insert(writers.len());
+            writers.push(CashBalanceJournalWriter::new(port, options.lot_costs));
+        }
+    }
+
+    let mut sorted_transaction: Vec<(usize, Transaction)> = categorized_resources
+        .transactions
+        .into_iter()
+        .filter(|t| options.as_of.is_none_or(|as_of| *t.date() <= as_of))
+        .enumerate()
+        .collect();
+    sorted_transaction.sort_by_key(|i| (*i.1.date(), !i.1.replays_first(), i.0));
+
+    let mut result: Vec<Vec<JournalEntry>> = writers.iter().map(|_| Vec::new()).collect();
+    let mut errors = Vec::new();
+    for (_, txn) in sorted_transaction {
+        match txn {
+            Transaction::Portfolio(port_id, t) => {
+                if let Some(&idx) = writer_index.get(&port_id) {
+                    match writers[idx].write(t) {
+                        Ok(entries) => result[idx].extend(entries),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            Transaction::Transfer(t) => {
+                let source = writer_index.get(&t.from).copied();
+                let destination = writer_index.get(&t.to).copied();
+                match (source, destination) {
+                    (Some(source), Some(destination)) if source != destination => {
+                        match transfer_between(&mut writers, source, destination, t) {
+                            Ok(entry) => result[source].push(entry),
+                            Err(e) => errors.push(e),
+                        }
+                    }
+                    (Some(_), Some(_)) => errors.push(JournalError::SamePortfolioTransfer {
+                        port_id: t.from,
+                        date: t.date,
+                    }),
+                    (None, _) => errors.push(JournalError::UnknownPortfolio {
+                        port_id: t.from,
+                        date: t.date,
+                    }),
+                    (_, None) => errors.push(JournalError::UnknownPortfolio {
+                        port_id: t.to,
+                        date: t.date,
+                    }),
+                }
+            }
+        }
+    }
+    let mut sections = Vec::new();
+    let mut start = 0;
+    for (writer, entries) in writers.iter().zip(&result) {
+        sections.push((writer.port.port_id.clone(), start..start + entries.len()));
+        start += entries.len();
+    }
+    let mut entries: Vec<JournalEntry> = result.into_iter().flatten().collect();
+    if options.balance_assertions {
+        let accounts: HashSet<&Account> = writers
+            .iter()
+            .flat_map(|w| {
+                [
+                    &w.port.accounts.cash_account,
+                    &w.port.accounts.position_account,
+                ]
+            })
+            .collect();
+        assert_balances(&mut entries, &accounts, options.assertion_order);
+    }
+    JournalBuild {
+        entries,
+        sections,
+        errors,
+        holdings: writers.iter().flat_map(|w| w.holdings()).collect(),
+        disposals: writers.into_iter().flat_map(|w| w.disposals).collect(),
+    }
+}
+
+/// Attach running balances to the postings of `accounts`, accumulated in `order`.
+/// Date order differs from the output order for settlements and dividend
+/// payments, and for entries of several portfolios sharing an account.
+fn assert_balances(
+    entries: &mut [JournalEntry],
+    accounts: &HashSet<&Account>,
+    order: AssertionOrder,
+) {
+    let mut indices: Vec<usize> = (0..entries.len()).collect();
+    match order {
+        AssertionOrder::Date => indices.sort_by_key(|&idx| entries[idx].date),
+        AssertionOrder::File => {}
+        AssertionOrder::FilePerYear => indices.sort_by_key(|&idx| entries[idx].date.0.year()),
+    }
+
+    let mut balances: HashMap<(Account, Commodity), Decimal> = HashMap::new();
+    for idx in indices {
+        for posting in &mut entries[idx].postings {
+            if !accounts.contains(&posting.account) {
+                continue;
+            }
+            if let Some((commodity, amount)) = &posting.amount {
+                let balance = balances
+                    .entry((posting.account.clone(), commodity.clone()))
+                    .or_default();
+                *balance += amount.0;
+                posting.assertion = Some(CommodityAmount(*balance));
+            }
         }
     }
-    result
+}
+
+/// Market prices implied by the resources: the price of every buy and sell, and
+/// every explicit `Price`. Only one price is kept per date, commodity and
+/// currency. An explicit price wins over trades, and of several trades that day
+/// the last one replayed wins, trades of a date being replayed in input order.
+/// Sorted by date, commodity then currency.
+pub fn market_prices(resources: &[Resource]) -> Vec<Price> {
+    let mut base_currencies: HashMap<&PortId, &Commodity> = HashMap::new();
+    for r in resources {
+        if let Resource::CashBalancePortfolio(port) = r {
+            base_currencies
+                .entry(&port.port_id)
+                .or_insert(&port.base_currency);
+        }
+    }
+    let trade_price = |port_id: &PortId,
+                       date: &Date,
+                       commodity: &Commodity,
+                       price: &CommodityPrice,
+                       currency: &Option<Commodity>| {
+        let currency = currency
+            .as_ref()
+            .or(base_currencies.get(port_id).copied())?;
+        Some(Price {
+            date: *date,
+            commodity: commodity.clone(),
+            price: price.clone(),
+            currency: currency.clone(),
+        })
+    };
+
+    let mut trades: Vec<Price> = Vec::new();
+    let mut explicit: Vec<Price> = Vec::new();
+    for r in resources {
+        match r {
+            Resource::Buy(i) => {
+                let t = &i.detail;
+                trades.extend(trade_price(
+                    &i.port_id,
+                    &t.date,
+                    &t.commodity,
+                    &t.price,
+                    &t.currency,
+                ));
+            }
+            Resource::Sell(i) => {
+                let t = &i.detail;
+                trades.extend(trade_price(
+                    &i.port_id,
+                    &t.date,
+                    &t.commodity,
+                    &t.price,
+                    &t.currency,
+                ));
+            }
+            Resource::Price(p) => explicit.push(p.clone()),
+            _ => {}
+        }
+    }
+    // stable, so trades of the same date keep their input order
+    trades.sort_by_key(|p| p.date);
+
+    let mut prices: BTreeMap<(Date, Commodity, Commodity), Price> = BTreeMap::new();
+    for price in trades.into_iter().chain(explicit) {
+        prices.insert(
+            (price.date, price.commodity.clone(), price.currency.clone()),
+            price,
+        );
+    }
+    prices.into_values().collect()
 }
 
 fn categorize_resources(resources: Vec<Resource>) -> CategorizedResources {
     let mut portfolios: Vec<CashBalancePortfolio> = Vec::new();
-    let mut transactions: HashMap<PortId, Vec<cb::Transaction>> = HashMap::new();
+    let mut transactions: Vec<Transaction> = Vec::new();
     for r in resources {
         match r {
             Resource::CashBalancePortfolio(port) => {
                 portfolios.push(*port);
             }
             Resource::Deposit(i) => {
-                let tx = i.detail.into();
-                if let Some(txs) = transactions.get_mut(&i.port_id) {
-                    txs.push(tx);
-                } else {
-                    transactions.insert(i.port_id, vec![tx]);
-                }
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
             }
             Resource::Withdraw(i) => {
-                let tx = i.detail.into();
-                if let Some(txs) = transactions.get_mut(&i.port_id) {
-                    txs.push(tx);
-                } else {
-                    transactions.insert(i.port_id, vec![tx]);
-                }
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
             }
             Resource::Buy(i) => {
-                let tx = i.detail.into();
-                if let Some(txs) = transactions.get_mut(&i.port_id) {
-                    txs.push(tx);
-                } else {
-                    transactions.insert(i.port_id, vec![tx]);
-                }
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
             }
             Resource::Sell(i) => {
-                let tx = i.detail.into();
-                if let Some(txs) = transactions.get_mut(&i.port_id) {
-                    txs.push(tx);
-                } else {
-                    transactions.insert(i.port_id, vec![tx]);
-                }
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
             }
             Resource::Interest(i) => {
-                let tx = i.detail.into();
-                if let Some(txs) = transactions.get_mut(&i.port_id) {
-                    txs.push(tx);
-                } else {
-                    transactions.insert(i.port_id, vec![tx]);
-                }
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
             }
+            Resource::Dividend(i) => {
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
+            }
+            Resource::Split(i) => {
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
+            }
+            Resource::StockDividend(i) => {
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
+            }
+            Resource::FxConversion(i) => {
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
+            }
+            Resource::TransferIn(i) => {
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
+            }
+            Resource::TransferOut(i) => {
+                transactions.push(Transaction::Portfolio(i.port_id, i.detail.into()));
+            }
+            Resource::PortfolioTransfer(t) => {
+                transactions.push(Transaction::Transfer(t));
+            }
+            // prices value positions but never move them
+            Resource::Price(_) => {}
+            // includes are already expanded while loading the input
+            Resource::Include(_) => {}
         }
     }
 
@@ -127,51 +593,216 @@ fn categorize_resources(resources: Vec<Resource>) -> CategorizedResources {
     }
 }
 
+/// Move cash and lots from one portfolio to another as a single entry. The
+/// destination is checked before anything is taken out of the source so that a
+/// failing transfer leaves both inventories untouched.
+fn transfer_between(
+    writers: &mut [CashBalanceJournalWriter],
+    source: usize,
+    destination: usize,
+    transfer: PortfolioTransfer,
+) -> Result<JournalEntry, JournalError> {
+    if transfer.cash.is_none() && transfer.position.is_none() {
+        return Err(JournalError::EmptyTransfer {
+            port_id: transfer.from,
+            date: transfer.date,
+        });
+    }
+
+    let mut postings = Vec::new();
+    let mut description = Vec::new();
+    if let Some(cash) = &transfer.cash {
+        let currency = writers[source].currency(&transfer.currency).clone();
+        postings.extend([
+            Posting::new(&writers[source].port.accounts.cash_account)
+                .with_amount((&currency, -cash)),
+            Posting::new(&writers[destination].port.accounts.cash_account)
+                .with_amount((&currency, cash)),
+        ]);
+        description.push(format!("{} {}", cash.0, currency.0));
+    }
+    if let Some(position) = &transfer.position {
+        if let Some(currency) = writers[source].lot_currency(&position.commodity) {
+            writers[destination].with_inventory(&position.commodity, |w, inventory| {
+                w.check_lot_currency(
+                    inventory.as_ref(),
+                    &currency,
+                    &position.commodity,
+                    &transfer.date,
+                )
+            })?;
+        }
+        let used_lots = writers[source].with_inventory(&position.commodity, |w, inventory| {
+            w.take_lots(
+                inventory,
+                &position.commodity,
+                &transfer.date,
+                &position.volume,
+                &position.lots,
+            )
+        })?;
+        let moved_comment = lots_comment(&used_lots);
+        if writers[source].lot_costs {
+            let mut moved_out = writers[source].restated_lots(&position.commodity, &used_lots, &[]);
+            with_first_comment(&mut moved_out, moved_comment);
+            let moved_in =
+                writers[destination].with_inventory(&position.commodity, |w, inventory| {
+                    let before = inventory.inventory().clone();
+                    for lot in used_lots {
+                        inventory.push(lot);
+                    }
+                    w.restated_lots(&position.commodity, &before, inventory.inventory())
+                });
+            postings.extend(moved_out);
+            postings.extend(moved_in);
+        } else {
+            writers[destination].with_inventory(&position.commodity, |_, inventory| {
+                for lot in used_lots {
+                    inventory.push(lot);
+                }
+            });
+            postings.extend([
+                Posting::new(&writers[source].port.accounts.position_account)
+                    .with_amount((&position.commodity, -&position.volume))
+                    .with_comment(moved_comment),
+                Posting::new(&writers[destination].port.accounts.position_account)
+                    .with_amount((&position.commodity, &position.volume)),
+            ]);
+        }
+        description.push(format!("{} {}", position.commodity.0, position.volume.0));
+    }
+
+    let comment = transfer
+        .comment
+        .as_ref()
+        .map(|c| format!(" ({c})"))
+        .unwrap_or_default();
+    Ok(JournalEntry {
+        date: transfer.date,
+        description: format!(
+            "Transfer {} -> {} {}{}",
+            transfer.from.0,
+            transfer.to.0,
+            description.join(", "),
+            comment
+        ),
+        postings,
+        inventory: None,
+    })
+}
+
 trait JournalWriter<T> {
-    fn to_journal_entries(&self, transactions: Vec<T>) -> Vec<JournalEntry>;
+    fn write(&mut self, transaction: T) -> Result<Vec<JournalEntry>, JournalError>;
 }
 
 struct CashBalanceJournalWriter {
     port: CashBalancePortfolio,
+    lot_costs: bool,
+    inventories: HashMap<Commodity, Box<dyn Inventory>>,
+    disposals: Vec<Disposal>,
 }
 
 impl JournalWriter<cb::Transaction> for CashBalanceJournalWriter {
-    fn to_journal_entries(&self, transactions: Vec<cb::Transaction>) -> Vec<JournalEntry> {
-        let mut sorted_transaction: Vec<(usize, cb::Transaction)> =
-            transactions.into_iter().enumerate().collect();
-        sorted_transaction.sort_by_key(|i| (i.1.date().clone(), i.0));
-
-        let mut inventories: HashMap<Commodity, Box<dyn Inventory>> = HashMap::new();
-        let mut result: Vec<JournalEntry> = Vec::new();
-        for (_, txn) in sorted_transaction {
-            match txn {
-                cb::Transaction::Deposit(t) => result.push(self.generate_deposit(t)),
-                cb::Transaction::Withdraw(t) => result.push(self.generate_withdraw(t)),
-                cb::Transaction::Buy(t) => {
-                    let inventory = match inventories.entry(t.commodity.clone()) {
-                        Entry::Occupied(e) => e.into_mut(),
-                        Entry::Vacant(e) => e.insert(Box::<FifoInventory>::default()), // TODO: support other cost basis
-                    };
-                    result.push(self.generate_buy(t, inventory));
-                }
-                cb::Transaction::Sell(t) => {
-                    let inventory = match inventories.entry(t.commodity.clone()) {
-                        Entry::Occupied(e) => e.into_mut(),
-                        Entry::Vacant(e) => e.insert(Box::<FifoInventory>::default()), // TODO: support other cost basis
-                    };
-                    result.extend(self.generate_sell(t, inventory))
-                }
-                cb::Transaction::InterestPayment(t) => {
-                    result.push(self.generate_interest_payment(t))
-                }
+    fn write(&mut self, transaction: cb::Transaction) -> Result<Vec<JournalEntry>, JournalError> {
+        match transaction {
+            cb::Transaction::Deposit(t) => Ok(vec![self.generate_deposit(t)]),
+            cb::Transaction::Withdraw(t) => Ok(vec![self.generate_withdraw(t)]),
+            cb::Transaction::Buy(t) => self
+                .with_inventory(&t.commodity.clone(), |w, i| w.generate_buy(t, i))
+                .map(|e| vec![e]),
+            cb::Transaction::Sell(t) => {
+                let (entries, disposals) =
+                    self.with_inventory(&t.commodity.clone(), |w, i| w.generate_sell(t, i))?;
+                self.disposals.extend(disposals);
+                Ok(entries)
+            }
+            cb::Transaction::InterestPayment(t) => Ok(vec![self.generate_interest_payment(t)]),
+            cb::Transaction::Dividend(t) => {
+                let held_volume: Decimal = self
+                    .inventories
+                    .get(&t.commodity)
+                    .map(|i| i.inventory().iter().map(|lot| lot.volume.0).sum())
+                    .unwrap_or_default();
+                self.generate_dividend(t, held_volume).map(|e| vec![e])
             }
+            cb::Transaction::Split(t) => self
+                .with_inventory(&t.commodity.clone(), |w, i| w.generate_split(t, i))
+                .map(|e| vec![e]),
+            cb::Transaction::StockDividend(t) => self
+                .with_inventory(&t.commodity.clone(), |w, i| w.generate_stock_dividend(t, i))
+                .map(|e| vec![e]),
+            cb::Transaction::FxConversion(t) => Ok(vec![self.generate_fx_conversion(t)]),
+            cb::Transaction::TransferIn(t) => self
+                .with_inventory(&t.commodity.clone(), |w, i| w.generate_transfer_in(t, i))
+                .map(|e| vec![e]),
+            cb::Transaction::TransferOut(t) => self
+                .with_inventory(&t.commodity.clone(), |w, i| w.generate_transfer_out(t, i))
+                .map(|e| vec![e]),
         }
+    }
+}
+
+impl CashBalanceJournalWriter {
+    fn new(port: CashBalancePortfolio, lot_costs: bool) -> Self {
+        Self {
+            port,
+            lot_costs,
+            inventories: HashMap::new(),
+            disposals: Vec::new(),
+        }
+    }
+
+    /// Run `f` with the inventory of `commodity`, created on first use with the
+    /// portfolio cost basis.
+    fn with_inventory<R>(
+        &mut self,
+        commodity: &Commodity,
+        f: impl FnOnce(&Self, &mut Box<dyn Inventory>) -> R,
+    ) -> R {
+        let mut inventory = self.inventory(commodity);
+        let result = f(self, &mut inventory);
+        self.put_inventory(commodity, inventory);
         result
     }
+
+    fn inventory(&mut self, commodity: &Commodity) -> Box<dyn Inventory> {
+        self.inventories
+            .remove(commodity)
+            .unwrap_or_else(|| inventory::new_inventory(&self.port.cost_basis))
+    }
+
+    fn put_inventory(&mut self, commodity: &Commodity, inventory: Box<dyn Inventory>) {
+        self.inventories.insert(commodity.clone(), inventory);
+    }
+
+    /// Currency of the lots currently held for `commodity`, if any.
+    fn lot_currency(&self, commodity: &Commodity) -> Option<Commodity> {
+        self.inventories
+            .get(commodity)
+            .and_then(|i| i.inventory().first())
+            .map(|lot| lot.currency.clone())
+    }
+
+    /// Non-empty inventories, sorted by commodity.
+    fn holdings(&self) -> Vec<Holding> {
+        let mut holdings: Vec<Holding> = self
+            .inventories
+            .iter()
+            .filter(|(_, inventory)| !inventory.inventory().is_empty())
+            .map(|(commodity, inventory)| Holding {
+                port_id: self.port.port_id.clone(),
+                commodity: commodity.clone(),
+                lots: inventory.inventory().clone(),
+            })
+            .collect();
+        holdings.sort_by(|a, b| a.commodity.cmp(&b.commodity));
+        holdings
+    }
 }
 
 impl CashBalanceJournalWriter {
     fn generate_deposit(&self, deposit: Deposit) -> JournalEntry {
+        let currency = self.currency(&deposit.currency);
         let comment = deposit
             .comment
             .as_ref()
@@ -182,15 +813,16 @@ impl CashBalanceJournalWriter {
             description: format!("Deposit{comment}"),
             postings: vec![
                 Posting::new(&self.port.accounts.cash_account)
-                    .with_amount((&self.port.base_currency, &deposit.amount)),
+                    .with_amount((currency, &deposit.amount)),
                 Posting::new(&self.port.accounts.net_investment_account)
-                    .with_amount((&self.port.base_currency, -deposit.amount)),
+                    .with_amount((currency, -deposit.amount)),
             ],
             inventory: None,
         }
     }
 
     fn generate_withdraw(&self, withdraw: Withdraw) -> JournalEntry {
+        let currency = self.currency(&withdraw.currency);
         let comment = withdraw
             .comment
             .as_ref()
@@ -201,91 +833,149 @@ impl CashBalanceJournalWriter {
             description: format!("Withdraw{comment}"),
             postings: vec![
                 Posting::new(&self.port.accounts.cash_account)
-                    .with_amount((&self.port.base_currency, -&withdraw.amount)),
+                    .with_amount((currency, -&withdraw.amount)),
                 Posting::new(&self.port.accounts.net_investment_account)
-                    .with_amount((&self.port.base_currency, withdraw.amount)),
+                    .with_amount((currency, withdraw.amount)),
             ],
             inventory: None,
         }
     }
 
-    fn generate_buy(&self, buy: Buy, inventory: &mut Box<dyn Inventory>) -> JournalEntry {
+    fn generate_buy(
+        &self,
+        buy: Buy,
+        inventory: &mut Box<dyn Inventory>,
+    ) -> Result<JournalEntry, JournalError> {
+        self.check_volume(&buy.volume, &buy.commodity, &buy.date)?;
+        let currency = self.currency(&buy.currency);
+        self.check_lot_currency(inventory.as_ref(), currency, &buy.commodity, &buy.date)?;
+        let fees =
+            buy.commission.clone().unwrap_or_default().0 + buy.vat.clone().unwrap_or_default().0;
+        let lot_price = if self.port.capitalize_fees {
+            CommodityPrice(buy.price.0 + fees / buy.volume.0)
+        } else {
+            buy.price.clone()
+        };
+        let cash_spent: Decimal = (Decimal::NEGATIVE_ONE * buy.price.0 * buy.volume.0) - fees;
+        let lot_cost = if self.port.capitalize_fees {
+            -cash_spent
+        } else {
+            buy.price.0 * buy.volume.0
+        };
         let lot = Lot {
-            date: buy.date.clone(),
-            price: buy.price.clone(),
+            date: buy.date,
+            price: lot_price,
             volume: buy.volume.clone(),
+            currency: currency.clone(),
+            id: buy.lot_id.clone(),
+            cost: CommodityAmount(lot_cost),
         };
+        let before = inventory.inventory().clone();
         inventory.push(lot);
-        let cash_spent: Decimal = (Decimal::NEGATIVE_ONE * buy.price.0 * buy.volume.0)
-            - buy.commission.clone().unwrap_or_default().0
-            - buy.vat.clone().unwrap_or_default().0;
         let comment = buy
             .comment
             .as_ref()
             .map(|c| format!(" ({c})"))
             .unwrap_or_default();
-        JournalEntry {
-            date: buy.date.clone(),
+        let mut postings = if self.lot_costs {
+            self.restated_lots(&buy.commodity, &before, inventory.inventory())
+        } else {
+            vec![Posting::new(&self.port.accounts.position_account)
+                .with_amount((&buy.commodity, buy.volume.clone()))]
+        };
+        postings.push(
+            Posting::new(&self.port.accounts.cash_account).with_amount((currency, cash_spent)),
+        );
+        postings.extend(self.fee_postings(currency, buy.commission, buy.vat));
+        if !self.lot_costs {
+            postings.push(Posting::new(&self.port.accounts.conversion_account));
+        }
+        Ok(JournalEntry {
+            date: buy.date,
             description: format!(
                 "Buy {} {} @{}{}",
                 buy.commodity.0, buy.volume.0, buy.price.0, comment
             ),
-            postings: vec![
-                Posting::new(&self.port.accounts.position_account)
-                    .with_amount((&buy.commodity, buy.volume)),
-                Posting::new(&self.port.accounts.cash_account)
-                    .with_amount((&self.port.base_currency, cash_spent)),
-                Posting::new(&self.port.accounts.commission_account)
-                    .with_amount((&self.port.base_currency, buy.commission.unwrap_or_default())),
-                Posting::new(&self.port.accounts.vat_account)
-                    .with_amount((&self.port.base_currency, buy.vat.unwrap_or_default())),
-                Posting::new(&self.port.accounts.conversion_account),
-            ],
+            postings,
             inventory: Some(inventory.inventory().clone()),
-        }
+        })
     }
 
-    fn generate_sell(&self, sell: Sell, inventory: &mut Box<dyn Inventory>) -> Vec<JournalEntry> {
-        let used_lots = inventory.pop(&sell.volume);
-        let cash_received = (sell.price.0 * sell.volume.0)
-            - sell.commission.clone().unwrap_or_default().0
-            - sell.vat.clone().unwrap_or_default().0;
-        let profit_loss: Decimal = used_lots
-            .iter()
-            .map(|lot| (sell.price.0 - lot.price.0) * lot.volume.0 * Decimal::NEGATIVE_ONE)
-            .sum();
-        let profit_loss_comment = used_lots
-            .iter()
-            .map(|lot| format!("{:?} @{:?}", lot.volume.0, lot.price.0))
-            .collect::<Vec<String>>()
-            .join(" / ");
+    fn generate_sell(
+        &self,
+        sell: Sell,
+        inventory: &mut Box<dyn Inventory>,
+    ) -> Result<(Vec<JournalEntry>, Vec<Disposal>), JournalError> {
+        self.check_volume(&sell.volume, &sell.commodity, &sell.date)?;
+        let currency = self.currency(&sell.currency);
+        self.check_lot_currency(inventory.as_ref(), currency, &sell.commodity, &sell.date)?;
+        let used_lots = self.take_lots(
+            inventory,
+            &sell.commodity,
+            &sell.date,
+            &sell.volume,
+            &sell.lots,
+        )?;
+        let fees =
+            sell.commission.clone().unwrap_or_default().0 + sell.vat.clone().unwrap_or_default().0;
+        let cash_received = (sell.price.0 * sell.volume.0) - fees;
+        let profit_loss: Decimal = if self.port.capitalize_fees {
+            let cost: Decimal = used_lots.iter().map(|lot| lot.cost.0).sum();
+            cost - cash_received
+        } else {
+            used_lots
+                .iter()
+                .map(|lot| lot.cost.0 - sell.price.0 * lot.volume.0)
+                .sum()
+        };
+        let profit_loss_comment = lots_comment(&used_lots);
+        let disposals = self.disposals(&sell, currency, fees, &used_lots);
         let comment = sell
             .comment
             .as_ref()
             .map(|c| format!(" ({c})"))
             .unwrap_or_default();
+        // at lot cost, every lot leaves the position separately and the profit
+        // or loss balances the entry without going through the conversion account
+        let mut postings: Vec<Posting> = if self.lot_costs {
+            used_lots
+                .iter()
+                .map(|lot| {
+                    let cost = LotCost {
+                        sold_at: (!self.port.capitalize_fees).then(|| sell.price.clone()),
+                        ..LotCost::new(lot)
+                    };
+                    Posting::new(&self.port.accounts.position_account)
+                        .with_amount((&sell.commodity, -&lot.volume))
+                        .with_cost(cost)
+                })
+                .collect()
+        } else {
+            vec![Posting::new(&self.port.accounts.position_account)
+                .with_amount((&sell.commodity, -&sell.volume))]
+        };
+        postings.push(
+            Posting::new(&self.port.accounts.cash_ar_account)
+                .with_amount((currency, cash_received)),
+        );
+        postings.extend(self.fee_postings(currency, sell.commission, sell.vat));
+        let mut profit_loss_posting = Posting::new(&self.port.accounts.protfit_loss_account)
+            .with_amount((currency, profit_loss))
+            .with_comment(profit_loss_comment);
+        if self.lot_costs && !self.port.capitalize_fees {
+            profit_loss_posting = profit_loss_posting.with_realized_gain();
+        }
+        postings.push(profit_loss_posting);
+        if !self.lot_costs {
+            postings.push(Posting::new(&self.port.accounts.conversion_account));
+        }
         let sell_entry = JournalEntry {
-            date: sell.date.clone(),
+            date: sell.date,
             description: format!(
                 "Sell {} {} @{}{}",
                 sell.commodity.0, sell.volume.0, sell.price.0, comment
             ),
-            postings: vec![
-                Posting::new(&self.port.accounts.position_account)
-                    .with_amount((&sell.commodity, -&sell.volume)),
-                Posting::new(&self.port.accounts.cash_ar_account)
-                    .with_amount((&self.port.base_currency, cash_received)),
-                Posting::new(&self.port.accounts.commission_account).with_amount((
-                    &self.port.base_currency,
-                    &sell.commission.unwrap_or_default(),
-                )),
-                Posting::new(&self.port.accounts.vat_account)
-                    .with_amount((&self.port.base_currency, sell.vat.unwrap_or_default())),
-                Posting::new(&self.port.accounts.protfit_loss_account)
-                    .with_amount((&self.port.base_currency, profit_loss))
-                    .with_comment(profit_loss_comment),
-                Posting::new(&self.port.accounts.conversion_account),
-            ],
+            postings,
             inventory: Some(inventory.inventory().clone()),
         };
         let settlement_entry = JournalEntry {
@@ -296,15 +986,149 @@ impl CashBalanceJournalWriter {
             ),
             postings: vec![
                 Posting::new(&self.port.accounts.cash_account)
-                    .with_amount((&self.port.base_currency, cash_received)),
+                    .with_amount((currency, cash_received)),
                 Posting::new(&self.port.accounts.cash_ar_account),
             ],
             inventory: None,
         };
-        vec![sell_entry, settlement_entry]
+        Ok((vec![sell_entry, settlement_entry], disposals))
+    }
+
+    /// Split a sale into one disposal per matched lot. Fees are shared by volume
+    /// at full precision, the last lot takes whatever is left so that the shares
+    /// add up to the fees whatever the currency's minor unit.
+    fn disposals(
+        &self,
+        sell: &Sell,
+        currency: &Commodity,
+        fees: Decimal,
+        used_lots: &[Lot],
+    ) -> Vec<Disposal> {
+        let mut remaining_fees = fees;
+        used_lots
+            .iter()
+            .enumerate()
+            .map(|(i, lot)| {
+                let lot_fees = if i + 1 == used_lots.len() {
+                    remaining_fees
+                } else {
+                    fees * lot.volume.0 / sell.volume.0
+                };
+                remaining_fees -= lot_fees;
+                let proceeds = sell.price.0 * lot.volume.0;
+                let cost = lot.cost.0;
+                let gain = if self.port.capitalize_fees {
+                    proceeds - lot_fees - cost
+                } else {
+                    proceeds - cost
+                };
+                Disposal {
+                    port_id: self.port.port_id.clone(),
+                    commodity: sell.commodity.clone(),
+                    currency: currency.clone(),
+                    date: sell.date,
+                    lot: lot.clone(),
+                    pooled: self.port.cost_basis == CostBasis::Average,
+                    proceeds,
+                    fees: lot_fees,
+                    gain,
+                }
+            })
+            .collect()
+    }
+
+    /// Take lots out of the inventory, by the explicit selections when given or
+    /// by the portfolio cost basis otherwise.
+    fn take_lots(
+        &self,
+        inventory: &mut Box<dyn Inventory>,
+        commodity: &Commodity,
+        date: &Date,
+        volume: &CommodityAmount,
+        selections: &Option<Vec<LotSelection>>,
+    ) -> Result<Vec<Lot>, JournalError> {
+        match selections {
+            Some(selections) => {
+                let selected_volume: Decimal = selections.iter().map(|s| s.volume.0).sum();
+                if selected_volume != volume.0 {
+                    return Err(JournalError::LotSelectionMismatch {
+                        port_id: self.port.port_id.clone(),
+                        commodity: commodity.clone(),
+                        date: *date,
+                        selected: selected_volume,
+                        requested: volume.0,
+                    });
+                }
+                inventory.pop_lots(selections)
+            }
+            None => inventory.pop(volume),
+        }
+        .map_err(|e| self.inventory_error(e, commodity, date))
+    }
+
+    fn inventory_error(
+        &self,
+        error: InventoryError,
+        commodity: &Commodity,
+        date: &Date,
+    ) -> JournalError {
+        let port_id = self.port.port_id.clone();
+        let commodity = commodity.clone();
+        let date = *date;
+        match error {
+            InventoryError::InsufficientVolume {
+                requested,
+                available,
+            } => JournalError::InsufficientVolume {
+                port_id,
+                commodity,
+                date,
+                requested,
+                available,
+            },
+            InventoryError::InsufficientLotVolume {
+                lot_id,
+                requested,
+                available,
+            } => JournalError::InsufficientLotVolume {
+                port_id,
+                commodity,
+                date,
+                lot_id,
+                requested,
+                available,
+            },
+            InventoryError::LotSelectionUnsupported => JournalError::LotSelectionUnsupported {
+                port_id,
+                commodity,
+                date,
+                cost_basis: self.port.cost_basis,
+            },
+        }
+    }
+
+    /// Commission and VAT postings of a trade. When fees are capitalized they are
+    /// already part of the lot cost (or netted from the proceeds), so nothing is
+    /// posted to the expense accounts and the conversion account absorbs them.
+    fn fee_postings(
+        &self,
+        currency: &Commodity,
+        commission: Option<CommodityAmount>,
+        vat: Option<CommodityAmount>,
+    ) -> Vec<Posting> {
+        if self.port.capitalize_fees {
+            return Vec::new();
+        }
+        vec![
+            Posting::new(&self.port.accounts.commission_account)
+                .with_amount((currency, commission.unwrap_or_default())),
+            Posting::new(&self.port.accounts.vat_account)
+                .with_amount((currency, vat.unwrap_or_default())),
+        ]
     }
 
     fn generate_interest_payment(&self, interest: InterestPayment) -> JournalEntry {
+        let currency = self.currency(&interest.currency);
         let comment = interest
             .comment
             .as_ref()
@@ -315,11 +1139,1451 @@ impl CashBalanceJournalWriter {
             description: format!("Interest Payment{comment}"),
             postings: vec![
                 Posting::new(&self.port.accounts.cash_account)
-                    .with_amount((&self.port.base_currency, &interest.amount)),
+                    .with_amount((currency, &interest.amount)),
                 Posting::new(&self.port.accounts.interest_account)
-                    .with_amount((&self.port.base_currency, -interest.amount)),
+                    .with_amount((currency, -interest.amount)),
             ],
             inventory: None,
         }
     }
+
+    fn generate_dividend(
+        &self,
+        dividend: Dividend,
+        held_volume: Decimal,
+    ) -> Result<JournalEntry, JournalError> {
+        let currency = self.currency(&dividend.currency);
+        let date = dividend.pay_date.unwrap_or(dividend.ex_date);
+        let dividend_account = self.required_account(
+            &self.port.accounts.dividend_account,
+            "dividend_account",
+            &date,
+        )?;
+        let (gross, description) = match (&dividend.per_share, &dividend.amount) {
+            (Some(per_share), None) => (
+                per_share.0 * held_volume,
+                format!(
+                    "Dividend {} {} @{}",
+                    dividend.commodity.0, held_volume, per_share.0
+                ),
+            ),
+            (None, Some(amount)) => (amount.0, format!("Dividend {}", dividend.commodity.0)),
+            _ => {
+                return Err(JournalError::InvalidDividendAmount {
+                    port_id: self.port.port_id.clone(),
+                    commodity: dividend.commodity,
+                    date,
+                })
+            }
+        };
+        let tax = dividend.withholding_tax.unwrap_or_default();
+        let comment = dividend
+            .comment
+            .as_ref()
+            .map(|c| format!(" ({c})"))
+            .unwrap_or_default();
+
+        let mut postings =
+            vec![Posting::new(&self.port.accounts.cash_account)
+                .with_amount((currency, gross - tax.0))];
+        if tax.0 != Decimal::ZERO {
+            let withholding_tax_account = self.required_account(
+                &self.port.accounts.withholding_tax_account,
+                "withholding_tax_account",
+                &date,
+            )?;
+            postings.push(Posting::new(withholding_tax_account).with_amount((currency, tax)));
+        }
+        postings.push(
+            Posting::new(dividend_account)
+                .with_amount((currency, -gross))
+                .with_comment(format!("ex-date {}", dividend.ex_date)),
+        );
+
+        Ok(JournalEntry {
+            date,
+            description: format!("{description}{comment}"),
+            postings,
+            inventory: None,
+        })
+    }
+
+    fn generate_split(
+        &self,
+        split: Split,
+        inventory: &mut Box<dyn Inventory>,
+    ) -> Result<JournalEntry, JournalError> {
+        if split.from <= Decimal::ZERO || split.to <= Decimal::ZERO {
+            return Err(JournalError::InvalidRatio {
+                port_id: self.port.port_id.clone(),
+                commodity: split.commodity,
+                date: split.date,
+                from: split.from,
+                to: split.to,
+            });
+        }
+        let before = inventory.inventory().clone();
+        inventory.split(split.from, split.to);
+        let old_volume: Decimal = before.iter().map(|lot| lot.volume.0).sum();
+        let new_volume: Decimal = inventory.inventory().iter().map(|lot| lot.volume.0).sum();
+        // at lot cost, every lot is closed and reopened at its new unit price
+        let postings = if self.lot_costs {
+            self.restated_lots(&split.commodity, &before, inventory.inventory())
+        } else {
+            vec![
+                Posting::new(&self.port.accounts.position_account)
+                    .with_amount((&split.commodity, -old_volume)),
+                Posting::new(&self.port.accounts.position_account)
+                    .with_amount((&split.commodity, new_volume)),
+                Posting::new(&self.port.accounts.conversion_account),
+            ]
+        };
+        let comment = split
+            .comment
+            .as_ref()
+            .map(|c| format!(" ({c})"))
+            .unwrap_or_default();
+        Ok(JournalEntry {
+            date: split.date,
+            description: format!(
+                "Split {} {}:{}{}",
+                split.commodity.0, split.from, split.to, comment
+            ),
+            postings,
+            inventory: Some(inventory.inventory().clone()),
+        })
+    }
+
+    fn generate_stock_dividend(
+        &self,
+        stock_dividend: StockDividend,
+        inventory: &mut Box<dyn Inventory>,
+    ) -> Result<JournalEntry, JournalError> {
+        let currency = self.currency(&stock_dividend.currency);
+        self.check_lot_currency(
+            inventory.as_ref(),
+            currency,
+            &stock_dividend.commodity,
+            &stock_dividend.date,
+        )?;
+        if stock_dividend.from <= Decimal::ZERO || stock_dividend.to <= Decimal::ZERO {
+            return Err(JournalError::InvalidRatio {
+                port_id: self.port.port_id.clone(),
+                commodity: stock_dividend.commodity,
+                date: stock_dividend.date,
+                from: stock_dividend.from,
+                to: stock_dividend.to,
+            });
+        }
+        let held_volume: Decimal = inventory.inventory().iter().map(|lot| lot.volume.0).sum();
+        let new_volume = (held_volume * stock_dividend.to / stock_dividend.from).floor();
+        let cash_in_lieu = stock_dividend.cash_in_lieu.unwrap_or_default();
+
+        let mut postings = Vec::new();
+        if new_volume > Decimal::ZERO {
+            let before = inventory.inventory().clone();
+            let price = stock_dividend.price.clone().unwrap_or_default();
+            let cost = price.0 * new_volume;
+            inventory.push(Lot {
+                date: stock_dividend.date,
+                cost: CommodityAmount(cost),
+                price,
+                volume: CommodityAmount(new_volume),
+                currency: currency.clone(),
+                id: None,
+            });
+            if self.lot_costs {
+                postings.extend(self.restated_lots(
+                    &stock_dividend.commodity,
+                    &before,
+                    inventory.inventory(),
+                ));
+                if cost != Decimal::ZERO {
+                    postings.push(
+                        Posting::new(&self.port.accounts.conversion_account)
+                            .with_amount((currency, -cost)),
+                    );
+                }
+            } else {
+                postings.push(
+                    Posting::new(&self.port.accounts.position_account)
+                        .with_amount((&stock_dividend.commodity, new_volume)),
+                );
+            }
+        }
+        if cash_in_lieu.0 != Decimal::ZERO {
+            let dividend_account = self.required_account(
+                &self.port.accounts.dividend_account,
+                "dividend_account",
+                &stock_dividend.date,
+            )?;
+            postings.extend([
+                Posting::new(&self.port.accounts.cash_account)
+                    .with_amount((currency, &cash_in_lieu))
+                    .with_comment("cash in lieu"),
+                Posting::new(dividend_account).with_amount((currency, -cash_in_lieu)),
+            ]);
+        }
+        if !self.lot_costs {
+            postings.push(Posting::new(&self.port.accounts.conversion_account));
+        }
+
+        let comment = stock_dividend
+            .comment
+            .as_ref()
+            .map(|c| format!(" ({c})"))
+            .unwrap_or_default();
+        Ok(JournalEntry {
+            date: stock_dividend.date,
+            description: format!(
+                "Stock Dividend {} {} {}:{}{}",
+                stock_dividend.commodity.0,
+                new_volume,
+                stock_dividend.from,
+                stock_dividend.to,
+                comment
+            ),
+            postings,
+            inventory: Some(inventory.inventory().clone()),
+        })
+    }
+
+    fn generate_fx_conversion(&self, fx: FxConversion) -> JournalEntry {
+        let to_amount = CommodityAmount(fx.from_amount.0 * fx.rate.0);
+        let comment = fx
+            .comment
+            .as_ref()
+            .map(|c| format!(" ({c})"))
+            .unwrap_or_default();
+        JournalEntry {
+            date: fx.date,
+            description: format!(
+                "FX Conversion {} {} -> {} {} @{}{}",
+                fx.from_amount.0, fx.from.0, to_amount.0, fx.to.0, fx.rate.0, comment
+            ),
+            postings: vec![
+                Posting::new(&self.port.accounts.cash_account)
+                    .with_amount((&fx.from, -&fx.from_amount)),
+                Posting::new(&self.port.accounts.conversion_account)
+                    .with_amount((&fx.from, &fx.from_amount)),
+                Posting::new(&self.port.accounts.conversion_account)
+                    .with_amount((&fx.to, -&to_amount)),
+                Posting::new(&self.port.accounts.cash_account).with_amount((&fx.to, to_amount)),
+            ],
+            inventory: None,
+        }
+    }
+
+    fn generate_transfer_in(
+        &self,
+        transfer: TransferIn,
+        inventory: &mut Box<dyn Inventory>,
+    ) -> Result<JournalEntry, JournalError> {
+        let transfer_account = self.required_account(
+            &self.port.accounts.transfer_account,
+            "transfer_account",
+            &transfer.date,
+        )?;
+        if transfer.lots.is_empty() {
+            return Err(JournalError::EmptyTransferIn {
+                port_id: self.port.port_id.clone(),
+                commodity: transfer.commodity,
+                date: transfer.date,
+            });
+        }
+        for lot in &transfer.lots {
+            self.check_volume(&lot.volume, &transfer.commodity, &transfer.date)?;
+        }
+        let currency = self.currency(&transfer.currency);
+        self.check_lot_currency(
+            inventory.as_ref(),
+            currency,
+            &transfer.commodity,
+            &transfer.date,
+        )?;
+        let lots: Vec<Lot> = transfer
+            .lots
+            .into_iter()
+            .map(|lot| Lot {
+                date: lot.date,
+                cost: CommodityAmount(lot.price.0 * lot.volume.0),
+                price: lot.price,
+                volume: lot.volume,
+                currency: currency.clone(),
+                id: lot.lot_id,
+            })
+            .collect();
+        let volume: Decimal = lots.iter().map(|lot| lot.volume.0).sum();
+        let cost: Decimal = lots.iter().map(|lot| lot.cost.0).sum();
+        let transferred_comment = lots_comment(&lots);
+        let before = inventory.inventory().clone();
+        for lot in lots {
+            inventory.push(lot);
+        }
+        // at lot cost, the transfer account carries the cost of the lots instead
+        // of their units, which the lot costs of the positions balance
+        let postings = if self.lot_costs {
+            let mut postings =
+                self.restated_lots(&transfer.commodity, &before, inventory.inventory());
+            with_first_comment(&mut postings, transferred_comment);
+            postings.push(Posting::new(transfer_account).with_amount((currency, -cost)));
+            postings
+        } else {
+            vec![
+                Posting::new(&self.port.accounts.position_account)
+                    .with_amount((&transfer.commodity, volume))
+                    .with_comment(transferred_comment),
+                Posting::new(transfer_account).with_amount((&transfer.commodity, -volume)),
+            ]
+        };
+        let comment = transfer
+            .comment
+            .as_ref()
+            .map(|c| format!(" ({c})"))
+            .unwrap_or_default();
+        Ok(JournalEntry {
+            date: transfer.date,
+            description: format!("Transfer In {} {}{}", transfer.commodity.0, volume, comment),
+            postings,
+            inventory: Some(inventory.inventory().clone()),
+        })
+    }
+
+    fn generate_transfer_out(
+        &self,
+        transfer: TransferOut,
+        inventory: &mut Box<dyn Inventory>,
+    ) -> Result<JournalEntry, JournalError> {
+        let transfer_account = self.required_account(
+            &self.port.accounts.transfer_account,
+            "transfer_account",
+            &transfer.date,
+        )?;
+        let used_lots = self.take_lots(
+            inventory,
+            &transfer.commodity,
+            &transfer.date,
+            &transfer.volume,
+            &transfer.lots,
+        )?;
+        let postings = if self.lot_costs {
+            let mut postings = self.restated_lots(&transfer.commodity, &used_lots, &[]);
+            with_first_comment(&mut postings, lots_comment(&used_lots));
+            if let Some(lot) = used_lots.first() {
+                let cost: Decimal = used_lots.iter().map(|lot| lot.cost.0).sum();
+                postings.push(Posting::new(transfer_account).with_amount((&lot.currency, cost)));
+            }
+            postings
+        } else {
+            vec![
+                Posting::new(&self.port.accounts.position_account)
+                    .with_amount((&transfer.commodity, -&transfer.volume))
+                    .with_comment(lots_comment(&used_lots)),
+                Posting::new(transfer_account).with_amount((&transfer.commodity, &transfer.volume)),
+            ]
+        };
+        let comment = transfer
+            .comment
+            .as_ref()
+            .map(|c| format!(" ({c})"))
+            .unwrap_or_default();
+        Ok(JournalEntry {
+            date: transfer.date,
+            description: format!(
+                "Transfer Out {} {}{}",
+                transfer.commodity.0, transfer.volume.0, comment
+            ),
+            postings,
+            inventory: Some(inventory.inventory().clone()),
+        })
+    }
+
+    /// Position postings at lot cost that take the lots held `before` an operation
+    /// to those held `after` it: lots that shrank or went away are reduced and
+    /// lots that grew or appeared are augmented. A pool restated by the average
+    /// cost basis or a lot rewritten by a split is thus closed and reopened, so
+    /// that a backend booking lots by their cost holds exactly the same lots.
+    fn restated_lots(&self, commodity: &Commodity, before: &[Lot], after: &[Lot]) -> Vec<Posting> {
+        let mut changes: Vec<Lot> = Vec::new();
+        let signed = before
+            .iter()
+            .map(|lot| (Decimal::NEGATIVE_ONE, lot))
+            .chain(after.iter().map(|lot| (Decimal::ONE, lot)));
+        for (sign, lot) in signed {
+            let same_lot = |change: &&mut Lot| {
+                (&change.date, &change.price, &change.id, &change.currency)
+                    == (&lot.date, &lot.price, &lot.id, &lot.currency)
+            };
+            match changes.iter_mut().find(same_lot) {
+                Some(change) => {
+                    change.volume.0 += sign * lot.volume.0;
+                    change.cost.0 += sign * lot.cost.0;
+                }
+                None => changes.push(Lot {
+                    volume: CommodityAmount(sign * lot.volume.0),
+                    cost: CommodityAmount(sign * lot.cost.0),
+                    ..lot.clone()
+                }),
+            }
+        }
+
+        let (reduced, augmented): (Vec<Lot>, Vec<Lot>) = changes
+            .into_iter()
+            .filter(|change| change.volume.0 != Decimal::ZERO)
+            .partition(|change| change.volume.0 < Decimal::ZERO);
+        reduced
+            .into_iter()
+            .chain(augmented)
+            .map(|change| {
+                let volume = change.volume.clone();
+                let lot = Lot {
+                    volume: CommodityAmount(change.volume.0.abs()),
+                    cost: CommodityAmount(change.cost.0.abs()),
+                    ..change
+                };
+                Posting::new(&self.port.accounts.position_account)
+                    .with_amount((commodity, volume))
+                    .with_cost(LotCost::new(&lot))
+            })
+            .collect()
+    }
+
+    /// Cash currency of a transaction, the portfolio base currency unless given.
+    fn currency<'a>(&'a self, currency: &'a Option<Commodity>) -> &'a Commodity {
+        currency.as_ref().unwrap_or(&self.port.base_currency)
+    }
+
+    /// An inventory holds lots of a single currency so that their costs can be
+    /// compared with the trade price.
+    fn check_lot_currency(
+        &self,
+        inventory: &dyn Inventory,
+        currency: &Commodity,
+        commodity: &Commodity,
+        date: &Date,
+    ) -> Result<(), JournalError> {
+        match inventory.inventory().first() {
+            Some(lot) if &lot.currency != currency => Err(JournalError::CurrencyMismatch {
+                port_id: self.port.port_id.clone(),
+                commodity: commodity.clone(),
+                date: *date,
+                held: lot.currency.clone(),
+                traded: currency.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// A trade of no volume, or a negative one, would book a lot that cannot be
+    /// sold or sell a lot that was never bought.
+    fn check_volume(
+        &self,
+        volume: &CommodityAmount,
+        commodity: &Commodity,
+        date: &Date,
+    ) -> Result<(), JournalError> {
+        match volume.0 > Decimal::ZERO {
+            true => Ok(()),
+            false => Err(JournalError::InvalidVolume {
+                port_id: self.port.port_id.clone(),
+                commodity: commodity.clone(),
+                date: *date,
+                volume: volume.0,
+            }),
+        }
+    }
+
+    fn required_account<'a>(
+        &self,
+        account: &'a Option<Account>,
+        name: &'static str,
+        date: &Date,
+    ) -> Result<&'a Account, JournalError> {
+        account
+            .as_ref()
+            .ok_or_else(|| JournalError::MissingAccount {
+                port_id: self.port.port_id.clone(),
+                date: *date,
+                account: name,
+            })
+    }
+}
+
+fn with_first_comment(postings: &mut [Posting], comment: String) {
+    if let Some(first) = postings.first_mut() {
+        first.comment = Some(comment);
+    }
+}
+
+fn lots_comment(lots: &[Lot]) -> String {
+    lots.iter()
+        .map(|lot| match &lot.id {
+            Some(id) => format!("{:?} @{:?} ({})", lot.volume.0, lot.price.0, id.0),
+            None => format!("{:?} @{:?}", lot.volume.0, lot.price.0),
+        })
+        .collect::<Vec<String>>()
+        .join(" / ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    const PORTFOLIO: &str = r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  cost_basis: Fifo
+  accounts:
+    cash_account: assets:cash
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+    dividend_account: income:dividend
+    withholding_tax_account: expenses:withholding_tax
+    transfer_account: equity:transfer
+"#;
+
+    /// Resources of the `---` separated YAML documents.
+    fn resources(yaml: &str) -> Vec<Resource> {
+        serde_yaml::Deserializer::from_str(yaml)
+            .map(|doc| Resource::deserialize(doc).unwrap())
+            .collect()
+    }
+
+    fn build_ok(yaml: &str, options: &BuildOptions) -> JournalBuild {
+        let build = build(resources(yaml), options);
+        assert!(build.errors.is_empty(), "{:?}", build.errors);
+        build
+    }
+
+    #[test]
+    fn dividend_ignores_lots_bought_on_the_ex_date() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: PTT, price: 30, volume: 100}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-20, commodity: PTT, price: 31, volume: 50}}
+---
+kind: Dividend
+spec:
+  port_id: port
+  detail: {{ex_date: 2023-01-20, pay_date: 2023-02-10, commodity: PTT, per_share: 2}}
+"#
+        );
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        let dividend = build
+            .entries
+            .iter()
+            .find(|e| e.description.starts_with("Dividend"))
+            .unwrap();
+        assert_eq!(dividend.date, "2023-02-10".parse().unwrap());
+        assert_eq!(
+            dividend.postings[0].amount,
+            Some((Commodity("THB".into()), CommodityAmount(200.into())))
+        );
+    }
+
+    #[test]
+    fn every_sell_beyond_the_holding_is_reported() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: PTT, price: 30, volume: 100}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: DELTA, price: 70, volume: 10}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-05, commodity: PTT, price: 32, volume: 150}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-06, commodity: DELTA, price: 75, volume: 12.5}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-09, commodity: PTT, price: 33, volume: 40}}
+"#
+        );
+
+        let build = build(resources(&yaml), &BuildOptions::default());
+
+        let errors: Vec<(&str, &str, Date, Decimal, Decimal)> = build
+            .errors
+            .iter()
+            .map(|e| match e {
+                JournalError::InsufficientVolume {
+                    port_id,
+                    commodity,
+                    date,
+                    requested,
+                    available,
+                } => (
+                    &port_id.0[..],
+                    &commodity.0[..],
+                    *date,
+                    *requested,
+                    *available,
+                ),
+                e => panic!("unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "port",
+                    "PTT",
+                    "2023-01-05".parse().unwrap(),
+                    150.into(),
+                    100.into()
+                ),
+                (
+                    "port",
+                    "DELTA",
+                    "2023-01-06".parse().unwrap(),
+                    "12.5".parse().unwrap(),
+                    10.into()
+                ),
+            ]
+        );
+        // a failed sell leaves the holding alone for the sells after it
+        assert!(build
+            .entries
+            .iter()
+            .any(|e| e.date == "2023-01-09".parse().unwrap()));
+    }
+
+    #[test]
+    fn trades_of_no_volume_are_rejected() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: PTT, price: 30, volume: 0, commission: 5}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: PTT, price: 30, volume: 100}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-04, commodity: PTT, price: 32, volume: -10}}
+"#
+        );
+
+        let build = build(resources(&yaml), &BuildOptions::default());
+
+        let messages: Vec<String> = build.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "portfolio port, PTT on 2023-01-02: volume 0 must be positive",
+                "portfolio port, PTT on 2023-01-04: volume -10 must be positive",
+            ]
+        );
+        assert_eq!(build.entries.len(), 1);
+        assert_eq!(build.holdings[0].lots.len(), 1);
+    }
+
+    #[test]
+    fn sale_fees_shared_by_lot_add_up_to_the_fees() {
+        let buy = |date: &str| {
+            format!(
+                r#"
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: {date}, commodity: ABC, price: 100, currency: JPY, volume: 1}}"#
+            )
+        };
+        let yaml = format!(
+            r#"{PORTFOLIO}{}{}{}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-02-01, commodity: ABC, price: 120, currency: JPY, volume: 3, commission: 100}}
+"#,
+            buy("2023-01-02"),
+            buy("2023-01-03"),
+            buy("2023-01-04")
+        );
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        assert_eq!(build.disposals.len(), 3);
+        let fees: Decimal = build.disposals.iter().map(|d| d.fees).sum();
+        assert_eq!(fees, Decimal::from(100));
+        assert!(build.disposals[0].fees.scale() > 2);
+    }
+
+    #[test]
+    fn market_prices_keep_one_price_per_currency() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-05, commodity: ABC, price: 11, volume: 1}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: ABC, price: 10, volume: 1}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-05, commodity: ABC, price: 12, volume: 1}}
+---
+kind: Price
+spec: {{date: 2023-01-05, commodity: ABC, price: 0.35, currency: USD}}
+"#
+        );
+
+        let prices: Vec<(String, String, String)> = market_prices(&resources(&yaml))
+            .into_iter()
+            .map(|p| (p.date.to_string(), p.price.0.to_string(), p.currency.0))
+            .collect();
+
+        assert_eq!(
+            prices,
+            vec![
+                ("2023-01-02".into(), "10".into(), "THB".into()),
+                ("2023-01-05".into(), "12".into(), "THB".into()),
+                ("2023-01-05".into(), "0.35".into(), "USD".into()),
+            ]
+        );
+    }
+
+    /// Date, volume and cost of the lots of `commodity` held at the end.
+    fn held_lots(build: &JournalBuild, commodity: &str) -> Vec<(String, Decimal, Decimal)> {
+        build
+            .holdings
+            .iter()
+            .filter(|h| h.commodity.0 == commodity)
+            .flat_map(|h| &h.lots)
+            .map(|lot| (lot.date.to_string(), lot.volume.0, lot.cost.0))
+            .collect()
+    }
+
+    /// Account and amount of every posting of the entry described as `description`.
+    fn postings(build: &JournalBuild, description: &str) -> Vec<(String, Option<String>)> {
+        build
+            .entries
+            .iter()
+            .find(|e| e.description.starts_with(description))
+            .unwrap_or_else(|| panic!("no entry {description}"))
+            .postings
+            .iter()
+            .map(|p| {
+                let amount = p.amount.as_ref().map(|(c, a)| format!("{} {}", c.0, a.0));
+                (p.account.0.clone(), amount)
+            })
+            .collect()
+    }
+
+    fn stock_dividend(detail: &str) -> String {
+        format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: PTT, price: 30, volume: 105}}
+---
+kind: StockDividend
+spec:
+  port_id: port
+  detail: {{date: 2023-03-01, commodity: PTT, from: 10, to: 1{detail}}}
+"#
+        )
+    }
+
+    #[test]
+    fn stock_dividend_issues_whole_shares_and_pays_the_fraction_in_cash() {
+        let yaml = stock_dividend(", price: 1, cash_in_lieu: 15");
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        // 105 shares at 1 for 10 entitle to 10.5, the half share is paid in cash
+        assert_eq!(
+            postings(&build, "Stock Dividend PTT 10 10:1"),
+            vec![
+                ("assets:positions".into(), Some("PTT 10".into())),
+                ("assets:cash".into(), Some("THB 15".into())),
+                ("income:dividend".into(), Some("THB -15".into())),
+                ("equity:conversion".into(), None),
+            ]
+        );
+        assert_eq!(
+            held_lots(&build, "PTT"),
+            vec![
+                ("2023-01-02".into(), 105.into(), 3150.into()),
+                ("2023-03-01".into(), 10.into(), 10.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn stock_dividend_without_price_issues_lots_at_no_cost() {
+        let yaml = stock_dividend("");
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        assert_eq!(
+            postings(&build, "Stock Dividend PTT 10 10:1"),
+            vec![
+                ("assets:positions".into(), Some("PTT 10".into())),
+                ("equity:conversion".into(), None),
+            ]
+        );
+        assert_eq!(
+            held_lots(&build, "PTT"),
+            vec![
+                ("2023-01-02".into(), 105.into(), 3150.into()),
+                ("2023-03-01".into(), 10.into(), 0.into()),
+            ]
+        );
+
+        let lot_costs = BuildOptions {
+            lot_costs: true,
+            ..Default::default()
+        };
+        let build = build_ok(&yaml, &lot_costs);
+        let booked = book(&build.entries, &["assets:positions"]);
+        let mut booked: Vec<(Date, Decimal, Decimal)> = booked
+            .iter()
+            .map(|((_, _, date, unit, _), volume)| (*date, unit.0, *volume))
+            .collect();
+        booked.sort();
+        assert_eq!(
+            booked,
+            vec![
+                ("2023-01-02".parse().unwrap(), 30.into(), 105.into()),
+                ("2023-03-01".parse().unwrap(), 0.into(), 10.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn stock_dividend_joins_the_average_cost_pool() {
+        let yaml = stock_dividend(", price: 2").replace("cost_basis: Fifo", "cost_basis: Average");
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        // the pool keeps one lot, dated by the latest addition
+        assert_eq!(
+            held_lots(&build, "PTT"),
+            vec![("2023-03-01".into(), 115.into(), 3170.into())]
+        );
+        let pool = &build.holdings[0].lots[0];
+        assert_eq!(pool.price.0, Decimal::from(3170) / Decimal::from(115));
+    }
+
+    #[test]
+    fn fx_conversion_moves_cash_between_currencies() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: FxConversion
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, from: THB, from_amount: 3400, to: USD, rate: 0.03}}
+"#
+        );
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        assert_eq!(
+            postings(&build, "FX Conversion 3400 THB -> 102.00 USD @0.03"),
+            vec![
+                ("assets:cash".into(), Some("THB -3400".into())),
+                ("equity:conversion".into(), Some("THB 3400".into())),
+                ("equity:conversion".into(), Some("USD -102.00".into())),
+                ("assets:cash".into(), Some("USD 102.00".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn trades_outside_the_base_currency_keep_their_currency() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: AAPL, price: 150, currency: USD, volume: 2, commission: 1}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-05, commodity: AAPL, price: 160, currency: USD, volume: 1}}
+"#
+        );
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        let cash: Vec<(String, Option<String>)> = postings(&build, "Buy AAPL")
+            .into_iter()
+            .chain(postings(&build, "Sell AAPL"))
+            .filter(|(_, amount)| amount.as_ref().is_some_and(|a| !a.starts_with("AAPL")))
+            .collect();
+        assert_eq!(
+            cash,
+            vec![
+                ("assets:cash".into(), Some("USD -301".into())),
+                ("expenses:commission".into(), Some("USD 1".into())),
+                ("expenses:vat".into(), Some("USD 0".into())),
+                ("assets:cash_ar".into(), Some("USD 160".into())),
+                ("expenses:commission".into(), Some("USD 0".into())),
+                ("expenses:vat".into(), Some("USD 0".into())),
+                ("income:profit_loss".into(), Some("USD -10".into())),
+            ]
+        );
+        let currencies: Vec<&str> = build.holdings[0]
+            .lots
+            .iter()
+            .map(|lot| &lot.currency.0[..])
+            .collect();
+        assert_eq!(currencies, vec!["USD"]);
+    }
+
+    #[test]
+    fn lots_of_a_commodity_are_held_in_one_currency() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: AAPL, price: 150, currency: USD, volume: 2}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: AAPL, price: 5100, volume: 1}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-04, commodity: AAPL, price: 5200, currency: THB, volume: 1}}
+"#
+        );
+
+        let build = build(resources(&yaml), &BuildOptions::default());
+
+        let messages: Vec<String> = build.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "portfolio port, AAPL on 2023-01-03: traded in THB but inventory is held in USD",
+                "portfolio port, AAPL on 2023-01-04: traded in THB but inventory is held in USD",
+            ]
+        );
+        assert_eq!(
+            held_lots(&build, "AAPL"),
+            vec![("2023-01-02".into(), 2.into(), 300.into())]
+        );
+    }
+
+    #[test]
+    fn transferred_lots_keep_their_original_dates_and_costs() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: TransferIn
+spec:
+  port_id: port
+  detail:
+    date: 2023-01-10
+    commodity: PTT
+    lots:
+      - {{date: 2021-05-01, price: 25, volume: 100, lot_id: A}}
+      - {{date: 2022-03-01, price: 28, volume: 50}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-02-01, commodity: PTT, price: 30, volume: 120}}
+"#
+        );
+
+        let build = build_ok(&yaml, &BuildOptions::default());
+
+        assert_eq!(
+            postings(&build, "Transfer In PTT 150"),
+            vec![
+                ("assets:positions".into(), Some("PTT 150".into())),
+                ("equity:transfer".into(), Some("PTT -150".into())),
+            ]
+        );
+        let disposals: Vec<(String, Option<&str>, Decimal, Decimal)> = build
+            .disposals
+            .iter()
+            .map(|d| {
+                let lot_id = d.lot.id.as_ref().map(|id| &id.0[..]);
+                (d.lot.date.to_string(), lot_id, d.lot.volume.0, d.gain)
+            })
+            .collect();
+        assert_eq!(
+            disposals,
+            vec![
+                ("2021-05-01".into(), Some("A"), 100.into(), 500.into()),
+                ("2022-03-01".into(), None, 20.into(), 40.into()),
+            ]
+        );
+        assert_eq!(
+            held_lots(&build, "PTT"),
+            vec![("2022-03-01".into(), 30.into(), 840.into())]
+        );
+    }
+
+    #[test]
+    fn transfer_in_needs_lots_of_some_volume() {
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+kind: TransferIn
+spec:
+  port_id: port
+  detail: {{date: 2023-01-10, commodity: PTT, lots: []}}
+---
+kind: TransferIn
+spec:
+  port_id: port
+  detail:
+    date: 2023-01-11
+    commodity: PTT
+    lots: [{{date: 2021-05-01, price: 25, volume: 0}}]
+"#
+        );
+
+        let build = build(resources(&yaml), &BuildOptions::default());
+
+        let messages: Vec<String> = build.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "portfolio port, PTT on 2023-01-10: transfer in has no lots",
+                "portfolio port, PTT on 2023-01-11: volume 0 must be positive",
+            ]
+        );
+        assert!(build.entries.is_empty());
+    }
+
+    #[test]
+    fn transfer_moves_lots_and_cash_into_an_average_cost_pool() {
+        let pool = PORTFOLIO
+            .replace("port_id: port", "port_id: pool")
+            .replace("cost_basis: Fifo", "cost_basis: Average")
+            .replace("assets:", "assets:pool:");
+        let yaml = format!(
+            r#"{PORTFOLIO}
+---
+{pool}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: PTT, price: 30, volume: 100}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: PTT, price: 36, volume: 50}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: AAPL, price: 150, currency: USD, volume: 2}}
+---
+kind: Buy
+spec:
+  port_id: pool
+  detail: {{date: 2023-01-04, commodity: PTT, price: 33, volume: 10}}
+---
+kind: Buy
+spec:
+  port_id: pool
+  detail: {{date: 2023-01-04, commodity: AAPL, price: 5000, volume: 1}}
+---
+kind: PortfolioTransfer
+spec:
+  date: 2023-01-10
+  from: port
+  to: pool
+  cash: 1000
+  position: {{commodity: PTT, volume: 120}}
+---
+kind: PortfolioTransfer
+spec:
+  date: 2023-01-11
+  from: port
+  to: pool
+  position: {{commodity: AAPL, volume: 1}}
+---
+kind: Sell
+spec:
+  port_id: pool
+  detail: {{date: 2023-01-20, commodity: PTT, price: 35, volume: 130}}
+"#
+        );
+
+        let build = build(resources(&yaml), &BuildOptions::default());
+
+        let messages: Vec<String> = build.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec!["portfolio pool, AAPL on 2023-01-11: traded in USD but inventory is held in THB"]
+        );
+        assert_eq!(
+            postings(&build, "Transfer port -> pool 1000 THB, PTT 120"),
+            vec![
+                ("assets:cash".into(), Some("THB -1000".into())),
+                ("assets:pool:cash".into(), Some("THB 1000".into())),
+                ("assets:positions".into(), Some("PTT -120".into())),
+                ("assets:pool:positions".into(), Some("PTT 120".into())),
+            ]
+        );
+        // the pool takes in 100 at 30 and 20 at 36 by FIFO on top of 10 at 33,
+        // and sells all 130 at 35 for the gain over their combined cost
+        let gains: Vec<(&str, Decimal, Decimal)> = build
+            .disposals
+            .iter()
+            .map(|d| (&d.port_id.0[..], d.lot.cost.0, d.gain))
+            .collect();
+        assert_eq!(gains, vec![("pool", 4050.into(), 500.into())]);
+        let held: Vec<(&str, &str, Decimal)> = build
+            .holdings
+            .iter()
+            .flat_map(|h| h.lots.iter().map(move |lot| (h, lot)))
+            .map(|(h, lot)| (&h.port_id.0[..], &h.commodity.0[..], lot.volume.0))
+            .collect();
+        assert_eq!(
+            held,
+            vec![
+                ("port", "AAPL", 2.into()),
+                ("port", "PTT", 30.into()),
+                ("pool", "AAPL", 1.into()),
+            ]
+        );
+    }
+
+    type BookedLots = HashMap<(Account, Commodity, Date, CommodityPrice, Option<LotId>), Decimal>;
+
+    /// Book lot-cost postings the way Beancount does: every position posting
+    /// names a lot, a reduction must find that lot held with enough units, and
+    /// every entry balances at cost with at most one commodity left to an
+    /// elided posting.
+    fn book(entries: &[JournalEntry], position_accounts: &[&str]) -> BookedLots {
+        let mut sorted: Vec<&JournalEntry> = entries.iter().collect();
+        sorted.sort_by_key(|e| e.date);
+
+        let mut booked = BookedLots::new();
+        for entry in sorted {
+            let mut residuals: HashMap<&Commodity, Decimal> = HashMap::new();
+            for p in &entry.postings {
+                let Some((commodity, amount)) = &p.amount else {
+                    continue;
+                };
+                if !position_accounts.contains(&p.account.0.as_str()) {
+                    *residuals.entry(commodity).or_default() += amount.0;
+                    continue;
+                }
+                let cost = p.cost.as_ref().unwrap_or_else(|| {
+                    panic!("{}: position posting without cost", entry.description)
+                });
+                *residuals.entry(&cost.currency).or_default() += cost.unit.0 * amount.0;
+                let key = (
+                    p.account.clone(),
+                    commodity.clone(),
+                    cost.date,
+                    cost.unit.clone(),
+                    cost.id.clone(),
+                );
+                let held = booked.entry(key.clone()).or_default();
+                if amount.0 < Decimal::ZERO {
+                    assert!(
+                        *held >= -amount.0,
+                        "{}: reduces {} of {key:?} but only {held} is booked",
+                        entry.description,
+                        -amount.0
+                    );
+                }
+                *held += amount.0;
+                if *held == Decimal::ZERO {
+                    booked.remove(&key);
+                }
+            }
+
+            let unbalanced: Vec<_> = residuals
+                .into_iter()
+                .filter(|(_, residual)| residual.abs() > Decimal::new(1, 10))
+                .collect();
+            let elided = entry.postings.iter().any(|p| p.amount.is_none());
+            assert!(
+                unbalanced.is_empty() || (elided && unbalanced.len() == 1),
+                "{}: unbalanced {unbalanced:?}",
+                entry.description
+            );
+        }
+        booked
+    }
+
+    #[test]
+    fn lot_costs_book_the_lots_held() {
+        let average = PORTFOLIO.replace(
+            "cost_basis: Fifo",
+            "cost_basis: Average\n  capitalize_fees: true",
+        );
+        let other = PORTFOLIO
+            .replace("port_id: port", "port_id: other")
+            .replace("assets:", "assets:other:");
+        let yaml = format!(
+            r#"{average}
+---
+{other}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, commodity: ABC, price: 10, volume: 100, commission: 7}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: ABC, price: 13, volume: 50, commission: 3}}
+---
+kind: TransferIn
+spec:
+  port_id: other
+  detail:
+    date: 2023-01-05
+    commodity: ABC
+    lots: [{{date: 2022-12-01, price: 3, volume: 30, lot_id: x}}]
+---
+kind: Split
+spec:
+  port_id: port
+  detail: {{date: 2023-01-10, commodity: ABC, from: 1, to: 3}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-15, commodity: ABC, price: 5, volume: 100, commission: 2}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-16, commodity: ABC, price: 4, volume: 70}}
+---
+kind: PortfolioTransfer
+spec:
+  date: 2023-01-20
+  from: port
+  to: other
+  position: {{commodity: ABC, volume: 50}}
+---
+kind: StockDividend
+spec:
+  port_id: other
+  detail: {{date: 2023-01-25, commodity: ABC, from: 10, to: 1, price: 2}}
+---
+kind: Sell
+spec:
+  port_id: other
+  detail: {{date: 2023-01-30, commodity: ABC, price: 5, volume: 40}}
+---
+kind: TransferOut
+spec:
+  port_id: other
+  detail: {{date: 2023-01-31, commodity: ABC, volume: 10}}
+"#
+        );
+        let options = BuildOptions {
+            lot_costs: true,
+            ..Default::default()
+        };
+
+        let build = build_ok(&yaml, &options);
+        let booked = book(
+            &build.entries,
+            &["assets:positions", "assets:other:positions"],
+        );
+
+        let mut held = BookedLots::new();
+        for holding in &build.holdings {
+            let account = match holding.port_id.0.as_str() {
+                "port" => "assets:positions",
+                _ => "assets:other:positions",
+            };
+            for lot in &holding.lots {
+                let key = (
+                    Account(account.into()),
+                    holding.commodity.clone(),
+                    lot.date,
+                    lot.price.clone(),
+                    lot.id.clone(),
+                );
+                *held.entry(key).or_default() += lot.volume.0;
+            }
+        }
+        assert_eq!(booked, held);
+        assert_eq!(build.holdings.len(), 2);
+    }
+
+    /// Asserted balances of `account`, in output order.
+    fn assertions(build: &JournalBuild, account: &str) -> Vec<Decimal> {
+        build
+            .entries
+            .iter()
+            .flat_map(|e| &e.postings)
+            .filter(|p| p.account.0 == account)
+            .filter_map(|p| p.assertion.as_ref().map(|a| a.0))
+            .collect()
+    }
+
+    fn asserting(order: AssertionOrder) -> BuildOptions {
+        BuildOptions {
+            balance_assertions: true,
+            assertion_order: order,
+            ..Default::default()
+        }
+    }
+
+    fn trades() -> String {
+        format!(
+            r#"{PORTFOLIO}
+---
+kind: Deposit
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, amount: 10000}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: PTT, price: 30, volume: 100}}
+---
+kind: Dividend
+spec:
+  port_id: port
+  detail: {{ex_date: 2023-01-05, pay_date: 2023-02-10, commodity: PTT, per_share: 2}}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-10, settlement_date: 2023-01-12, commodity: PTT, price: 35, volume: 40}}
+---
+kind: Split
+spec:
+  port_id: port
+  detail: {{date: 2023-01-20, commodity: PTT, from: 1, to: 2}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-25, commodity: PTT, price: 16, volume: 10}}
+"#
+        )
+    }
+
+    #[test]
+    fn balance_assertions_accumulate_by_date() {
+        let build = build_ok(&trades(), &asserting(AssertionOrder::Date));
+
+        // the dividend paid on 2023-02-10 is listed at its ex-date, and already
+        // includes the settlement of 2023-01-12 and the buy of 2023-01-25
+        let cash: Vec<Decimal> = [10000, 7000, 8440, 8400, 8240]
+            .into_iter()
+            .map(Decimal::from)
+            .collect();
+        assert_eq!(assertions(&build, "assets:cash"), cash);
+        let positions: Vec<Decimal> = [100, 60, 0, 120, 130]
+            .into_iter()
+            .map(Decimal::from)
+            .collect();
+        assert_eq!(assertions(&build, "assets:positions"), positions);
+        assert!(assertions(&build, "assets:cash_ar").is_empty());
+    }
+
+    #[test]
+    fn balance_assertions_accumulate_in_file_order() {
+        let build = build_ok(&trades(), &asserting(AssertionOrder::File));
+
+        let cash: Vec<Decimal> = [10000, 7000, 7200, 8600, 8440]
+            .into_iter()
+            .map(Decimal::from)
+            .collect();
+        assert_eq!(assertions(&build, "assets:cash"), cash);
+    }
+
+    #[test]
+    fn balance_assertions_of_a_shared_account_follow_the_order() {
+        let deposit = |port_id: &str, date: &str, amount: i64| {
+            format!(
+                r#"
+---
+kind: Deposit
+spec:
+  port_id: {port_id}
+  detail: {{date: {date}, amount: {amount}}}"#
+            )
+        };
+        let yaml = [
+            PORTFOLIO.to_string(),
+            "---".into(),
+            PORTFOLIO.replace("port_id: port", "port_id: other"),
+            deposit("port", "2023-12-30", 100),
+            deposit("port", "2024-01-05", 10),
+            deposit("other", "2023-06-01", 1000),
+        ]
+        .concat();
+
+        // entries are grouped by portfolio: port's two deposits, then other's
+        let cash = |order| -> Vec<i64> {
+            assertions(&build_ok(&yaml, &asserting(order)), "assets:cash")
+                .into_iter()
+                .map(|balance| balance.try_into().unwrap())
+                .collect()
+        };
+        assert_eq!(cash(AssertionOrder::Date), vec![1100, 1110, 1000]);
+        assert_eq!(cash(AssertionOrder::File), vec![100, 110, 1110]);
+        assert_eq!(cash(AssertionOrder::FilePerYear), vec![100, 1110, 1100]);
+    }
 }
@@ -2,11 +2,14 @@ use clap::Parser;
 
 mod cli;
 mod command;
-mod hledger;
 mod input;
 mod inventory;
 mod journal;
 mod model;
+mod output;
+mod render;
+mod report;
+mod validate;
 
 fn main() {
     let cli = cli::Cli::parse();
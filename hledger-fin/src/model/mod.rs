@@ -1,8 +1,17 @@
+use chrono::NaiveDate;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
-use std::ops::{Add, Neg, Sub};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    fmt,
+    ops::{Add, Neg, Sub},
+    str::FromStr,
+};
 
 pub mod port;
+pub mod price;
 pub mod txn;
 
 macro_rules! discrete_newtype {
@@ -89,7 +98,132 @@ macro_rules! scalar_newtype {
 
 discrete_newtype!(Account, String);
 discrete_newtype!(Commodity, String);
-discrete_newtype!(Date, String);
+discrete_newtype!(LotId, String);
 discrete_newtype!(PortId, String);
 scalar_newtype!(CommodityPrice, Decimal);
 scalar_newtype!(CommodityAmount, Decimal);
+
+/// Calendar date, validated when resources are parsed so that transactions sort
+/// chronologically rather than lexicographically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(pub NaiveDate);
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid date `{0}`, expected YYYY-MM-DD, YYYY/MM/DD or YYYY.MM.DD")]
+pub struct DateParseError(String);
+
+impl Date {
+    /// Accepted input formats, the same separators hledger accepts.
+    const INPUT_FORMATS: [&'static str; 3] = ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"];
+}
+
+impl FromStr for Date {
+    type Err = DateParseError;
+
+    /// Month and day must be zero-padded: chrono reads `2023-1-4` as well, and
+    /// only a date that formats back to its input is accepted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Self::INPUT_FORMATS
+            .iter()
+            .find_map(|format| {
+                NaiveDate::parse_from_str(s, format)
+                    .ok()
+                    .filter(|date| date.format(format).to_string() == s)
+            })
+            .map(Date)
+            .ok_or_else(|| DateParseError(s.to_string()))
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%d"))
+    }
+}
+
+impl From<&Date> for Date {
+    fn from(value: &Date) -> Self {
+        *value
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DateVisitor;
+
+        impl<'de> Visitor<'de> for DateVisitor {
+            type Value = Date;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a date")
+            }
+
+            // failing inside the visitor lets the parser point at the offending value
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(DateVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(year: i32, month: u32, day: u32) -> Date {
+        Date(NaiveDate::from_ymd_opt(year, month, day).unwrap())
+    }
+
+    #[test]
+    fn dates_parse_with_any_hledger_separator() {
+        for input in ["2023-01-04", "2023/01/04", "2023.01.04", " 2023-01-04 "] {
+            assert_eq!(input.parse::<Date>(), Ok(ymd(2023, 1, 4)), "{input}");
+        }
+    }
+
+    #[test]
+    fn invalid_or_unpadded_dates_are_rejected() {
+        for input in [
+            "2023-02-30",
+            "2023-1-4",
+            "2023-01-4",
+            "2023-01/04",
+            "04-01-2023",
+            "",
+        ] {
+            assert_eq!(
+                input.parse::<Date>(),
+                Err(DateParseError(input.to_string())),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn dates_display_and_serialize_as_iso() {
+        let date: Date = "2023/01/04".parse().unwrap();
+        assert_eq!(date.to_string(), "2023-01-04");
+        assert_eq!(serde_json::to_string(&date).unwrap(), r#""2023-01-04""#);
+    }
+
+    #[test]
+    fn dates_deserialize_through_the_same_parser() {
+        let date: Date = serde_yaml::from_str("2023.01.04").unwrap();
+        assert_eq!(date, ymd(2023, 1, 4));
+
+        let err = serde_yaml::from_str::<Date>("2023-02-30").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid date `2023-02-30`"),
+            "{err}"
+        );
+    }
+}
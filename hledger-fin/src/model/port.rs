@@ -5,6 +5,10 @@ use serde::{Deserialize, Serialize};
 pub struct CashBalancePortfolio {
     pub port_id: PortId,
     pub base_currency: Commodity,
+    #[serde(default)]
+    pub cost_basis: CostBasis,
+    #[serde(default)]
+    pub capitalize_fees: bool,
     pub accounts: CashBalancePortfolioAccounts,
 }
 
@@ -19,4 +23,57 @@ pub struct CashBalancePortfolioAccounts {
     pub vat_account: Account,
     pub protfit_loss_account: Account,
     pub interest_account: Account,
+    pub dividend_account: Option<Account>,
+    pub withholding_tax_account: Option<Account>,
+    pub transfer_account: Option<Account>,
+}
+
+/// What an account holds, as understood by hledger account types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountType {
+    Asset,
+    Equity,
+    Expense,
+    Revenue,
+}
+
+impl CashBalancePortfolioAccounts {
+    /// Every configured account, in declaration order.
+    pub fn all(&self) -> Vec<&Account> {
+        self.typed()
+            .into_iter()
+            .map(|(account, _)| account)
+            .collect()
+    }
+
+    /// Every configured account with its type, in declaration order.
+    pub fn typed(&self) -> Vec<(&Account, AccountType)> {
+        use AccountType::*;
+        [
+            (Some(&self.cash_account), Asset),
+            (Some(&self.cash_ar_account), Asset),
+            (Some(&self.position_account), Asset),
+            (Some(&self.net_investment_account), Equity),
+            (Some(&self.conversion_account), Equity),
+            (Some(&self.commission_account), Expense),
+            (Some(&self.vat_account), Expense),
+            (Some(&self.protfit_loss_account), Revenue),
+            (Some(&self.interest_account), Revenue),
+            (self.dividend_account.as_ref(), Revenue),
+            (self.withholding_tax_account.as_ref(), Expense),
+            (self.transfer_account.as_ref(), Equity),
+        ]
+        .into_iter()
+        .filter_map(|(account, account_type)| account.map(|a| (a, account_type)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CostBasis {
+    #[default]
+    Fifo,
+    Lifo,
+    Average,
+    Specific,
 }
@@ -0,0 +1,11 @@
+use super::{Commodity, CommodityPrice, Date};
+use serde::{Deserialize, Serialize};
+
+/// Market price of one unit of `commodity`, quoted in `currency`, as of `date`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Price {
+    pub date: Date,
+    pub commodity: Commodity,
+    pub price: CommodityPrice,
+    pub currency: Commodity,
+}
@@ -1,10 +1,12 @@
-use super::{Commodity, CommodityAmount, CommodityPrice, Date};
+use super::{Commodity, CommodityAmount, CommodityPrice, Date, LotId, PortId};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Deposit {
     pub date: Date,
     pub amount: CommodityAmount,
+    pub currency: Option<Commodity>,
     pub comment: Option<String>,
 }
 
@@ -12,6 +14,7 @@ pub struct Deposit {
 pub struct Withdraw {
     pub date: Date,
     pub amount: CommodityAmount,
+    pub currency: Option<Commodity>,
     pub comment: Option<String>,
 }
 
@@ -20,9 +23,11 @@ pub struct Buy {
     pub date: Date,
     pub commodity: Commodity,
     pub price: CommodityPrice,
+    pub currency: Option<Commodity>,
     pub volume: CommodityAmount,
     pub commission: Option<CommodityAmount>,
     pub vat: Option<CommodityAmount>,
+    pub lot_id: Option<LotId>,
     pub comment: Option<String>,
 }
 
@@ -32,19 +37,126 @@ pub struct Sell {
     pub settlement_date: Option<Date>,
     pub commodity: Commodity,
     pub price: CommodityPrice,
+    pub currency: Option<Commodity>,
     pub volume: CommodityAmount,
     pub commission: Option<CommodityAmount>,
     pub vat: Option<CommodityAmount>,
+    pub lots: Option<Vec<LotSelection>>,
     pub comment: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LotSelection {
+    pub lot_id: LotId,
+    pub volume: CommodityAmount,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InterestPayment {
     pub date: Date,
     pub amount: CommodityAmount,
+    pub currency: Option<Commodity>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dividend {
+    pub ex_date: Date,
+    pub pay_date: Option<Date>,
+    pub commodity: Commodity,
+    pub per_share: Option<CommodityPrice>,
+    pub amount: Option<CommodityAmount>,
+    pub currency: Option<Commodity>,
+    pub withholding_tax: Option<CommodityAmount>,
+    pub comment: Option<String>,
+}
+
+/// Every `from` shares held become `to` shares, e.g. `from: 1, to: 2` for a 2-for-1
+/// split or `from: 10, to: 1` for a 1-for-10 reverse split.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Split {
+    pub date: Date,
+    pub commodity: Commodity,
+    pub from: Decimal,
+    pub to: Decimal,
+    pub comment: Option<String>,
+}
+
+/// Bonus shares paid for every `from` shares held, e.g. `from: 10, to: 1`. Only
+/// whole shares are issued; the fraction is usually paid out as `cash_in_lieu`.
+/// New lots cost `price` per share, or nothing when it is not given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StockDividend {
+    pub date: Date,
+    pub commodity: Commodity,
+    pub from: Decimal,
+    pub to: Decimal,
+    pub price: Option<CommodityPrice>,
+    pub cash_in_lieu: Option<CommodityAmount>,
+    pub currency: Option<Commodity>,
+    pub comment: Option<String>,
+}
+
+/// Exchange `from_amount` of `from` cash into `to` cash at `rate` units of `to`
+/// per unit of `from`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FxConversion {
+    pub date: Date,
+    pub from: Commodity,
+    pub from_amount: CommodityAmount,
+    pub to: Commodity,
+    pub rate: CommodityPrice,
+    pub comment: Option<String>,
+}
+
+/// Position arriving from another broker with its original lots. Cash is not touched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferIn {
+    pub date: Date,
+    pub commodity: Commodity,
+    pub currency: Option<Commodity>,
+    pub lots: Vec<TransferLot>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferLot {
+    pub date: Date,
+    pub price: CommodityPrice,
+    pub volume: CommodityAmount,
+    pub lot_id: Option<LotId>,
+}
+
+/// Position leaving to another broker, taking its lots along. Cash is not touched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOut {
+    pub date: Date,
+    pub commodity: Commodity,
+    pub volume: CommodityAmount,
+    pub lots: Option<Vec<LotSelection>>,
     pub comment: Option<String>,
 }
 
+/// Cash and/or a position moved between two portfolios, keeping the cost basis
+/// of the moved lots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioTransfer {
+    pub date: Date,
+    pub from: PortId,
+    pub to: PortId,
+    pub cash: Option<CommodityAmount>,
+    pub currency: Option<Commodity>,
+    pub position: Option<TransferPosition>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferPosition {
+    pub commodity: Commodity,
+    pub volume: CommodityAmount,
+    pub lots: Option<Vec<LotSelection>>,
+}
+
 pub trait DatedTransaction {
     fn date(&self) -> &Date;
 }
@@ -79,6 +191,50 @@ impl DatedTransaction for InterestPayment {
     }
 }
 
+impl DatedTransaction for Dividend {
+    /// Entitlement is decided by the holding on the ex-date, so that is
+    /// when the dividend is processed even though it is booked on the pay date.
+    fn date(&self) -> &Date {
+        &self.ex_date
+    }
+}
+
+impl DatedTransaction for Split {
+    fn date(&self) -> &Date {
+        &self.date
+    }
+}
+
+impl DatedTransaction for StockDividend {
+    fn date(&self) -> &Date {
+        &self.date
+    }
+}
+
+impl DatedTransaction for FxConversion {
+    fn date(&self) -> &Date {
+        &self.date
+    }
+}
+
+impl DatedTransaction for TransferIn {
+    fn date(&self) -> &Date {
+        &self.date
+    }
+}
+
+impl DatedTransaction for TransferOut {
+    fn date(&self) -> &Date {
+        &self.date
+    }
+}
+
+impl DatedTransaction for PortfolioTransfer {
+    fn date(&self) -> &Date {
+        &self.date
+    }
+}
+
 macro_rules! portfolio_transaction {
     ($module:ident, $($txn:ident),+) => {
         pub mod $module {
@@ -112,4 +268,17 @@ macro_rules! portfolio_transaction {
     };
 }
 
-portfolio_transaction!(cashbalance, Deposit, Withdraw, Buy, Sell, InterestPayment);
+portfolio_transaction!(
+    cashbalance,
+    Deposit,
+    Withdraw,
+    Buy,
+    Sell,
+    InterestPayment,
+    Dividend,
+    Split,
+    StockDividend,
+    FxConversion,
+    TransferIn,
+    TransferOut
+);
@@ -0,0 +1,346 @@
+use crate::{
+    journal::JournalEntry,
+    model::{port::CashBalancePortfolio, price::Price, PortId},
+    render::Renderer,
+};
+use chrono::Datelike;
+use clap::ValueEnum;
+use std::{
+    collections::{BTreeMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Split {
+    /// One file per portfolio
+    Portfolio,
+    /// One file per year
+    Year,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OutputError {
+    #[error("unable to write {}: {source}", .path.display())]
+    FileIO {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("this output format has no include directive and cannot be split")]
+    SplitUnsupported,
+}
+
+/// A built journal, with its entries grouped by portfolio.
+pub struct Journal<'a> {
+    pub portfolios: &'a [CashBalancePortfolio],
+    pub entries: &'a [JournalEntry],
+    pub sections: &'a [(PortId, Range<usize>)],
+    pub prices: &'a [Price],
+    /// Prices written to a file of their own. Their commodities are declared
+    /// along with the others, but they are not placed among the entries.
+    pub external_prices: &'a [Price],
+}
+
+impl Journal<'_> {
+    /// The whole journal as a single file.
+    pub fn render(&self, renderer: &dyn Renderer) -> String {
+        let header = self.header(renderer);
+        let body = renderer.body(self.entries, self.prices);
+        match header.is_empty() {
+            true => body,
+            false => format!("{header}\n\n{body}"),
+        }
+    }
+
+    fn header(&self, renderer: &dyn Renderer) -> String {
+        let prices = [self.prices, self.external_prices].concat();
+        renderer.header(self.portfolios, self.entries, &prices)
+    }
+}
+
+/// Write the journal to `path`. With `split`, each part goes to its own file next
+/// to `path`, named after it with the part as suffix, and `path` holds the
+/// declarations and includes every part. Parts included by the previous `path`
+/// that are no longer written are removed, so regenerating never leaves stale
+/// parts behind.
+pub fn write_journal(
+    renderer: &dyn Renderer,
+    journal: &Journal,
+    path: &Path,
+    split: Option<Split>,
+) -> Result<(), OutputError> {
+    let previous_parts = included_parts(renderer, path);
+    let written_parts = write_parts(renderer, journal, path, split)?;
+    for part in previous_parts {
+        if !written_parts.contains(&part) {
+            std::fs::remove_file(&part)
+                .map_err(|source| OutputError::FileIO { path: part, source })?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the top file and its parts, returning the paths of the parts.
+fn write_parts(
+    renderer: &dyn Renderer,
+    journal: &Journal,
+    path: &Path,
+    split: Option<Split>,
+) -> Result<Vec<PathBuf>, OutputError> {
+    let Some(split) = split else {
+        write_if_changed(path, &journal.render(renderer))?;
+        return Ok(Vec::new());
+    };
+
+    // prices follow the entries of their year, or stay in the main file when
+    // parts are not tied to dates
+    let (parts, top_prices): (Vec<(String, String)>, &[Price]) = match split {
+        Split::Portfolio => {
+            let parts = journal
+                .sections
+                .iter()
+                .filter(|(_, range)| !range.is_empty())
+                .map(|(port_id, range)| {
+                    let body = renderer.body(&journal.entries[range.clone()], &[]);
+                    (port_id.0.clone(), body)
+                })
+                .collect();
+            (parts, journal.prices)
+        }
+        Split::Year => {
+            let mut years: BTreeMap<i32, (Vec<JournalEntry>, Vec<Price>)> = BTreeMap::new();
+            for e in journal.entries {
+                years.entry(e.date.0.year()).or_default().0.push(e.clone());
+            }
+            for p in journal.prices {
+                years.entry(p.date.0.year()).or_default().1.push(p.clone());
+            }
+            let parts = years
+                .into_iter()
+                .map(|(year, (entries, prices))| {
+                    (year.to_string(), renderer.body(&entries, &prices))
+                })
+                .collect();
+            (parts, &[])
+        }
+    };
+
+    let mut includes = Vec::new();
+    let mut part_paths = Vec::new();
+    for (name, body) in parts {
+        let part_path = part_path(path, &name);
+        let file_name = part_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        includes.push(
+            renderer
+                .include(&file_name)
+                .ok_or(OutputError::SplitUnsupported)?,
+        );
+        write_if_changed(&part_path, &body)?;
+        part_paths.push(part_path);
+    }
+
+    let header = journal.header(renderer);
+    let mut content = String::new();
+    if !header.is_empty() {
+        content += &format!("{header}\n\n");
+    }
+    if !includes.is_empty() {
+        content += &format!("{}\n\n", includes.join("\n"));
+    }
+    content += &renderer.body(&[], top_prices);
+    write_if_changed(path, &content)?;
+    Ok(part_paths)
+}
+
+/// Part files next to `path` that its current content includes.
+fn included_parts(renderer: &dyn Renderer, path: &Path) -> Vec<PathBuf> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let includes: HashSet<&str> = content.lines().collect();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(files) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut parts: Vec<PathBuf> = files
+        .filter_map(Result::ok)
+        .filter_map(|f| f.file_name().into_string().ok())
+        .filter(|file_name| is_part_name(path, file_name))
+        .filter(|file_name| {
+            renderer
+                .include(file_name)
+                .is_some_and(|include| includes.contains(include.as_str()))
+        })
+        .map(|file_name| path.with_file_name(file_name))
+        .collect();
+    parts.sort();
+    parts
+}
+
+/// Whether `file_name` is named like a part of `path`, see `part_path`.
+fn is_part_name(path: &Path, file_name: &str) -> bool {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let part = file_name.strip_prefix(&format!("{stem}-"));
+    let part = match path.extension() {
+        Some(ext) => part.and_then(|p| p.strip_suffix(&format!(".{}", ext.to_string_lossy()))),
+        None => part,
+    };
+    part.is_some_and(|p| !p.is_empty())
+}
+
+/// `journal.hledger` with part `2023` becomes `journal-2023.hledger`.
+fn part_path(path: &Path, part: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}-{part}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{part}"),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Replace the file atomically, through a temporary file renamed over it, and
+/// only when its content changes so that regenerating leaves timestamps and
+/// version control alone.
+pub fn write_if_changed(path: &Path, content: &str) -> Result<(), OutputError> {
+    if std::fs::read(path).is_ok_and(|existing| existing == content.as_bytes()) {
+        return Ok(());
+    }
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{file_name}.tmp"));
+    std::fs::write(&tmp_path, content)
+        .and_then(|_| std::fs::rename(&tmp_path, path))
+        .map_err(|source| {
+            let _ = std::fs::remove_file(&tmp_path);
+            OutputError::FileIO {
+                path: path.to_path_buf(),
+                source,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        journal::Posting,
+        model::{Account, Commodity, CommodityAmount, CommodityPrice},
+        render::OutputFormat,
+    };
+
+    /// A fresh directory under the system temp dir, named after the test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hledger-fin-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(date: &str) -> JournalEntry {
+        JournalEntry {
+            date: date.parse().unwrap(),
+            description: "Deposit".into(),
+            postings: vec![
+                Posting::new(Account("assets:cash".into()))
+                    .with_amount((Commodity("THB".into()), CommodityAmount(100.into()))),
+                Posting::new(Account("equity:net_invest".into())),
+            ],
+            inventory: None,
+        }
+    }
+
+    fn write(path: &Path, entries: &[JournalEntry], split: Option<Split>) {
+        let journal = Journal {
+            portfolios: &[],
+            entries,
+            sections: &[(PortId("port".into()), 0..entries.len())],
+            prices: &[],
+            external_prices: &[],
+        };
+        let renderer = OutputFormat::Hledger.renderer(false);
+        write_journal(renderer.as_ref(), &journal, path, split).unwrap();
+    }
+
+    fn modified(path: &Path) -> std::time::SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn rewriting_the_same_journal_leaves_files_untouched() {
+        let dir = temp_dir("unchanged");
+        let path = dir.join("journal.hledger");
+        let entries = [entry("2022-01-03"), entry("2023-01-03")];
+
+        write(&path, &entries, Some(Split::Year));
+        let first = [&path, &dir.join("journal-2022.hledger")].map(|p| modified(p));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write(&path, &entries, Some(Split::Year));
+
+        let second = [&path, &dir.join("journal-2022.hledger")].map(|p| modified(p));
+        assert_eq!(first, second);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parts_no_longer_written_are_removed() {
+        let dir = temp_dir("stale");
+        let path = dir.join("journal.hledger");
+        let unrelated = dir.join("journal-notes.txt");
+        std::fs::write(&unrelated, "notes").unwrap();
+
+        write(
+            &path,
+            &[entry("2022-01-03"), entry("2023-01-03")],
+            Some(Split::Year),
+        );
+        write(&path, &[entry("2023-01-03")], Some(Split::Year));
+
+        assert!(!dir.join("journal-2022.hledger").exists());
+        assert!(dir.join("journal-2023.hledger").exists());
+        assert!(unrelated.exists());
+
+        write(&path, &[entry("2023-01-03")], Some(Split::Portfolio));
+        assert!(!dir.join("journal-2023.hledger").exists());
+        assert!(dir.join("journal-port.hledger").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prices_written_elsewhere_are_still_declared() {
+        let entries = [entry("2023-01-03")];
+        let external_prices = [Price {
+            date: "2023-01-03".parse().unwrap(),
+            commodity: Commodity("PTT".into()),
+            price: CommodityPrice(35.into()),
+            currency: Commodity("EUR".into()),
+        }];
+        let journal = Journal {
+            portfolios: &[],
+            entries: &entries,
+            sections: &[(PortId("port".into()), 0..entries.len())],
+            prices: &[],
+            external_prices: &external_prices,
+        };
+        let renderer = OutputFormat::Hledger.renderer(true);
+
+        let rendered = journal.render(renderer.as_ref());
+
+        assert!(rendered.contains("commodity EUR 1000\n"));
+        assert!(rendered.contains("commodity PTT 1000\n"));
+        assert!(!rendered.contains("P 2023-01-03"));
+    }
+}
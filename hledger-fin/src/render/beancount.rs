@@ -0,0 +1,156 @@
+use super::{average, commodities, lots_summary, EntryRenderer, DATE_FORMAT};
+use crate::{
+    journal::JournalEntry,
+    model::{port::CashBalancePortfolio, price::Price, Account, Commodity},
+};
+use std::collections::BTreeSet;
+
+/// Beancount syntax. Comments become metadata and balance assertions are left
+/// out, Beancount only checks balances with separate `balance` directives.
+pub struct Beancount;
+
+impl EntryRenderer for Beancount {
+    fn header(
+        &self,
+        portfolios: &[CashBalancePortfolio],
+        entries: &[JournalEntry],
+        prices: &[Price],
+    ) -> String {
+        // everything is opened on the first day anything happens
+        let Some(date) = entries
+            .iter()
+            .map(|e| e.date)
+            .chain(prices.iter().map(|p| p.date))
+            .min()
+        else {
+            return String::new();
+        };
+        let date = date.0.format(DATE_FORMAT);
+
+        let mut lines = Vec::new();
+        let operating_currencies: BTreeSet<&Commodity> =
+            portfolios.iter().map(|p| &p.base_currency).collect();
+        for currency in operating_currencies {
+            lines.push(format!(
+                "option \"operating_currency\" \"{}\"",
+                commodity(currency)
+            ));
+        }
+        lines.push("".into());
+        for c in commodities(entries, prices) {
+            lines.push(format!("{date} commodity {}", commodity(c)));
+        }
+        lines.push("".into());
+        let mut opened = BTreeSet::new();
+        for port in portfolios {
+            for a in port.accounts.all() {
+                if opened.insert(a) {
+                    lines.push(format!("{date} open {}", account(a)));
+                }
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn entry(&self, entry: &JournalEntry) -> String {
+        let mut lines = vec![format!(
+            "{} * \"{}\"",
+            entry.date.0.format(DATE_FORMAT),
+            escape(&entry.description)
+        )];
+        if let Some(lots) = &entry.inventory {
+            let (total_lot, avg_cost) = average(lots);
+            lines.push(format!("  avg: \"{total_lot} @{avg_cost}\""));
+            lines.push(format!("  inventory: \"{}\"", escape(&lots_summary(lots))));
+        }
+
+        let accounts: Vec<String> = entry.postings.iter().map(|p| account(&p.account)).collect();
+        let max_account_len = accounts.iter().map(String::len).max().unwrap_or_default();
+        for (p, account) in entry.postings.iter().zip(&accounts) {
+            let amount_str = p
+                .amount
+                .as_ref()
+                .map(|(c, amount)| {
+                    let spaces = " ".repeat(max_account_len + 4 - account.len());
+                    format!("{spaces}{} {}", amount.0, commodity(c))
+                })
+                .unwrap_or_default();
+            let cost_str = p
+                .cost
+                .as_ref()
+                .map(|c| {
+                    let label =
+                        c.id.as_ref()
+                            .map(|id| format!(", \"{}\"", escape(&id.0)))
+                            .unwrap_or_default();
+                    let date = c.date.0.format(DATE_FORMAT);
+                    // a sale price is informational only, Beancount weighs
+                    // reductions at cost
+                    let price = c
+                        .sold_at
+                        .as_ref()
+                        .map(|p| format!(" @ {} {}", p.0, commodity(&c.currency)))
+                        .unwrap_or_default();
+                    // always per unit, even when the total is what was paid, so
+                    // that reductions name the lot the way it was booked
+                    let cost = format!(
+                        " {{{} {}, {date}{label}}}",
+                        c.unit.0,
+                        commodity(&c.currency)
+                    );
+                    cost + &price
+                })
+                .unwrap_or_default();
+            lines.push(format!("  {account}{amount_str}{cost_str}"));
+            if let Some(comment) = &p.comment {
+                lines.push(format!("    comment: \"{}\"", escape(comment)));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn price(&self, price: &Price) -> String {
+        format!(
+            "{} price {} {} {}",
+            price.date.0.format(DATE_FORMAT),
+            commodity(&price.commodity),
+            price.price.0,
+            commodity(&price.currency)
+        )
+    }
+
+    fn include(&self, path: &str) -> String {
+        format!("include \"{}\"", escape(path))
+    }
+}
+
+/// Beancount account components start with a capital letter and only hold
+/// letters, digits and dashes, so `income:stock:profit_loss` becomes
+/// `Income:Stock:Profit-loss`.
+fn account(account: &Account) -> String {
+    account
+        .0
+        .split(':')
+        .map(|component| {
+            let component: String = component
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect();
+            let mut chars = component.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => component,
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Beancount commodities are upper case.
+fn commodity(commodity: &Commodity) -> String {
+    commodity.0.to_uppercase()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,247 @@
+use super::{place_prices, Renderer};
+use crate::{journal::JournalEntry, model::price::Price};
+use serde::Serialize;
+
+/// One item of a data export. Entries are numbered from 1 in output order so
+/// that their postings can be grouped back together.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    Entry {
+        id: usize,
+        #[serde(flatten)]
+        entry: &'a JournalEntry,
+    },
+    Price(&'a Price),
+}
+
+/// Entries and prices in journal order.
+fn records<'a>(entries: &'a [JournalEntry], prices: &'a [Price]) -> Vec<Record<'a>> {
+    let placed = place_prices(entries, prices);
+    let mut records = Vec::new();
+    for (idx, (entry, prices)) in entries.iter().zip(&placed).enumerate() {
+        records.extend(prices.iter().map(|p| Record::Price(p)));
+        records.push(Record::Entry { id: idx + 1, entry });
+    }
+    records.extend(
+        placed
+            .last()
+            .into_iter()
+            .flatten()
+            .map(|p| Record::Price(p)),
+    );
+    records
+}
+
+/// A single JSON array of records.
+pub struct Json;
+
+impl Renderer for Json {
+    fn body(&self, entries: &[JournalEntry], prices: &[Price]) -> String {
+        serde_json::to_string_pretty(&records(entries, prices))
+            .expect("journal entries are always serializable")
+            + "\n"
+    }
+
+    fn prices(&self, prices: &[Price]) -> String {
+        serde_json::to_string_pretty(prices).expect("prices are always serializable") + "\n"
+    }
+}
+
+/// One JSON record per line.
+pub struct JsonLines;
+
+impl Renderer for JsonLines {
+    fn body(&self, entries: &[JournalEntry], prices: &[Price]) -> String {
+        records(entries, prices)
+            .iter()
+            .map(|r| serde_json::to_string(r).expect("journal entries are always serializable"))
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    fn prices(&self, prices: &[Price]) -> String {
+        prices
+            .iter()
+            .map(|p| serde_json::to_string(p).expect("prices are always serializable") + "\n")
+            .collect()
+    }
+}
+
+/// One row per posting. Prices have a different shape and only go to a
+/// separate prices file.
+pub struct Csv;
+
+#[derive(Serialize)]
+struct PostingRow<'a> {
+    entry_id: usize,
+    date: &'a str,
+    description: &'a str,
+    account: &'a str,
+    commodity: Option<&'a str>,
+    amount: Option<String>,
+    comment: Option<&'a str>,
+}
+
+impl Renderer for Csv {
+    fn body(&self, entries: &[JournalEntry], _prices: &[Price]) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for (idx, entry) in entries.iter().enumerate() {
+            let date = entry.date.to_string();
+            for p in &entry.postings {
+                writer
+                    .serialize(PostingRow {
+                        entry_id: idx + 1,
+                        date: &date,
+                        description: &entry.description,
+                        account: &p.account.0,
+                        commodity: p.amount.as_ref().map(|(c, _)| c.0.as_str()),
+                        amount: p.amount.as_ref().map(|(_, a)| a.0.to_string()),
+                        comment: p.comment.as_deref(),
+                    })
+                    .expect("postings are always serializable");
+            }
+        }
+        csv_output(writer)
+    }
+
+    fn prices(&self, prices: &[Price]) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for p in prices {
+            writer.serialize(p).expect("prices are always serializable");
+        }
+        csv_output(writer)
+    }
+}
+
+fn csv_output(writer: csv::Writer<Vec<u8>>) -> String {
+    let bytes = writer
+        .into_inner()
+        .expect("writing to memory does not fail");
+    String::from_utf8(bytes).expect("csv of utf-8 fields is utf-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::Resource,
+        journal::{build, market_prices, BuildOptions},
+    };
+    use serde::Deserialize;
+
+    const RESOURCES: &str = r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  cost_basis: Fifo
+  accounts:
+    cash_account: assets:cash
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+---
+kind: Deposit
+spec:
+  port_id: port
+  detail: {date: 2023-01-02, amount: 1000, comment: "opening, \"cash\""}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-03, commodity: PTT, price: 30.50, volume: 20, lot_id: first}
+"#;
+
+    fn journal() -> (Vec<JournalEntry>, Vec<Price>) {
+        let resources: Vec<Resource> = serde_yaml::Deserializer::from_str(RESOURCES)
+            .map(|doc| Resource::deserialize(doc).unwrap())
+            .collect();
+        let prices = market_prices(&resources);
+        let build = build(resources, &BuildOptions::default());
+        assert!(build.errors.is_empty(), "{:?}", build.errors);
+        (build.entries, prices)
+    }
+
+    #[test]
+    fn json_lines_tag_each_record_and_write_decimals_as_strings() {
+        let (entries, prices) = journal();
+
+        let lines = JsonLines.body(&entries, &prices);
+
+        let posting = |account: &str, amount: &str| match amount {
+            "" => format!(
+                r#"{{"account":"{account}","amount":null,"assertion":null,"cost":null,"realized_gain":false,"comment":null}}"#
+            ),
+            _ => {
+                let (commodity, quantity) = amount.split_once(' ').unwrap();
+                format!(
+                    r#"{{"account":"{account}","amount":{{"commodity":"{commodity}","quantity":"{quantity}"}},"assertion":null,"cost":null,"realized_gain":false,"comment":null}}"#
+                )
+            }
+        };
+        let expected = [
+            format!(
+                r#"{{"type":"entry","id":1,"date":"2023-01-02","description":"Deposit (opening, \"cash\")","postings":[{},{}],"inventory":null}}"#,
+                posting("assets:cash", "THB 1000"),
+                posting("equity:net_invest", "THB -1000"),
+            ),
+            r#"{"type":"price","date":"2023-01-03","commodity":"PTT","price":"30.5","currency":"THB"}"#
+                .to_string(),
+            format!(
+                r#"{{"type":"entry","id":2,"date":"2023-01-03","description":"Buy PTT 20 @30.5","postings":[{},{},{},{},{}],"inventory":[{{"date":"2023-01-03","price":"30.5","volume":"20","currency":"THB","id":"first","cost":"610.0"}}]}}"#,
+                posting("assets:positions", "PTT 20"),
+                posting("assets:cash", "THB -610.0"),
+                posting("expenses:commission", "THB 0"),
+                posting("expenses:vat", "THB 0"),
+                posting("equity:conversion", ""),
+            ),
+        ];
+        assert_eq!(lines.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn json_is_an_array_of_the_same_records() {
+        let (entries, prices) = journal();
+
+        let json = Json.body(&entries, &prices);
+
+        let records: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let lines: Vec<serde_json::Value> = JsonLines
+            .body(&entries, &prices)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records, serde_json::Value::Array(lines));
+        assert!(json.starts_with("[\n  {\n    \"type\": \"entry\",\n    \"id\": 1,\n"));
+        assert!(json.ends_with("\n]\n"));
+    }
+
+    #[test]
+    fn csv_has_one_row_per_posting() {
+        let (entries, prices) = journal();
+
+        assert_eq!(
+            Csv.body(&entries, &prices),
+            "\
+entry_id,date,description,account,commodity,amount,comment
+1,2023-01-02,\"Deposit (opening, \"\"cash\"\")\",assets:cash,THB,1000,
+1,2023-01-02,\"Deposit (opening, \"\"cash\"\")\",equity:net_invest,THB,-1000,
+2,2023-01-03,Buy PTT 20 @30.5,assets:positions,PTT,20,
+2,2023-01-03,Buy PTT 20 @30.5,assets:cash,THB,-610.0,
+2,2023-01-03,Buy PTT 20 @30.5,expenses:commission,THB,0,
+2,2023-01-03,Buy PTT 20 @30.5,expenses:vat,THB,0,
+2,2023-01-03,Buy PTT 20 @30.5,equity:conversion,,,
+"
+        );
+        assert_eq!(
+            Csv.prices(&prices),
+            "date,commodity,price,currency\n2023-01-03,PTT,30.5,THB\n"
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use super::{average, commodities, lots_summary, EntryRenderer, DATE_FORMAT};
+use crate::{
+    journal::JournalEntry,
+    model::{
+        port::{AccountType, CashBalancePortfolio},
+        price::Price,
+        Account, Commodity,
+    },
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// hledger syntax. With `declarations`, accounts and commodities are declared
+/// ahead of the entries as `--strict` requires.
+pub struct HLedger {
+    pub declarations: bool,
+}
+
+impl EntryRenderer for HLedger {
+    fn header(
+        &self,
+        portfolios: &[CashBalancePortfolio],
+        entries: &[JournalEntry],
+        prices: &[Price],
+    ) -> String {
+        if !self.declarations {
+            return String::new();
+        }
+
+        // display precision is the most decimal places of the amounts posted or
+        // quoted in a market price. Lot costs and realized gains are left out, a
+        // share of the cost of a pool or a split lot runs to the full `Decimal`
+        // scale
+        let mut precisions: BTreeMap<&Commodity, u32> = commodities(entries, prices)
+            .into_iter()
+            .chain(portfolios.iter().map(|p| &p.base_currency))
+            .map(|c| (c, 0))
+            .collect();
+        let gain_accounts: HashSet<&Account> = portfolios
+            .iter()
+            .map(|p| &p.accounts.protfit_loss_account)
+            .collect();
+        let posted = entries
+            .iter()
+            .flat_map(|e| &e.postings)
+            .filter(|p| !gain_accounts.contains(&p.account))
+            .filter_map(|p| p.amount.as_ref())
+            .map(|(commodity, amount)| (commodity, &amount.0));
+        let quoted = prices.iter().map(|p| (&p.currency, &p.price.0));
+        for (commodity, amount) in posted.chain(quoted) {
+            let precision = precisions.entry(commodity).or_default();
+            *precision = (*precision).max(amount.scale());
+        }
+        let mut lines: Vec<String> = precisions
+            .into_iter()
+            .map(|(commodity, precision)| {
+                format!(
+                    "commodity {} {:.*}",
+                    commodity.0,
+                    precision as usize,
+                    Decimal::from(1000)
+                )
+            })
+            .collect();
+
+        let mut declared = BTreeSet::new();
+        let accounts: Vec<String> = portfolios
+            .iter()
+            .flat_map(|port| port.accounts.typed())
+            .filter(|(account, _)| declared.insert(*account))
+            .map(|(account, account_type)| {
+                let account_type = match account_type {
+                    AccountType::Asset => "A",
+                    AccountType::Equity => "E",
+                    AccountType::Expense => "X",
+                    AccountType::Revenue => "R",
+                };
+                format!("account {}  ; type: {account_type}", account.0)
+            })
+            .collect();
+        if !lines.is_empty() && !accounts.is_empty() {
+            lines.push("".into());
+        }
+        lines.extend(accounts);
+        lines.join("\n")
+    }
+
+    fn entry(&self, entry: &JournalEntry) -> String {
+        let inventory = entry.inventory.as_ref().map_or("".into(), |lots| {
+            let inventory_str = lots_summary(lots);
+            let (total_lot, avg_cost) = average(lots);
+            format!("  ; avg {total_lot} @{avg_cost} ; inventory [{inventory_str}]")
+        });
+        let max_account_len = entry
+            .postings
+            .iter()
+            .map(|p| p.account.0.len())
+            .max()
+            .unwrap_or_default();
+        let postings = entry
+            .postings
+            .iter()
+            .map(|p| {
+                let amount_str = p
+                    .amount
+                    .as_ref()
+                    .map(|a| format!("{} {}", a.0 .0, a.1 .0))
+                    .unwrap_or_else(|| "".into());
+                let cost_str = p
+                    .cost
+                    .as_ref()
+                    .map(|c| {
+                        let price = match (&c.sold_at, &c.total) {
+                            (Some(sold_at), _) => format!("@ {} {}", c.currency.0, sold_at.0),
+                            (None, Some(total)) => format!("@@ {} {}", c.currency.0, total.0),
+                            (None, None) => format!("@ {} {}", c.currency.0, c.unit.0),
+                        };
+                        format!(
+                            " {{{} {}}} [{}] {price}",
+                            c.currency.0,
+                            c.unit.0,
+                            c.date.0.format(DATE_FORMAT)
+                        )
+                    })
+                    .unwrap_or_default();
+                let assertion_str = match (&p.amount, &p.assertion) {
+                    (Some((commodity, _)), Some(balance)) => {
+                        format!(" = {} {}", commodity.0, balance.0)
+                    }
+                    _ => "".into(),
+                };
+                let comment_str = p
+                    .comment
+                    .as_ref()
+                    .map(|c| format!("  ; {c}"))
+                    .unwrap_or_else(|| "".into());
+                let spaces = " ".repeat(max_account_len + 4 - p.account.0.len());
+                format!(
+                    "    {}{}{}{}{}{}",
+                    p.account.0, spaces, amount_str, cost_str, assertion_str, comment_str
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!(
+            "{date} {desc}{inventory}\n{postings}",
+            date = entry.date.0.format(DATE_FORMAT),
+            desc = entry.description,
+            postings = postings,
+            inventory = inventory
+        )
+    }
+
+    fn price(&self, price: &Price) -> String {
+        format!(
+            "P {} {} {} {}",
+            price.date.0.format(DATE_FORMAT),
+            price.commodity.0,
+            price.currency.0,
+            price.price.0
+        )
+    }
+
+    fn include(&self, path: &str) -> String {
+        format!("include {path}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Renderer;
+    use crate::{
+        input::Resource,
+        journal::{build, BuildOptions},
+    };
+    use serde::Deserialize;
+
+    fn resources(yaml: &str) -> Vec<Resource> {
+        serde_yaml::Deserializer::from_str(yaml)
+            .map(|doc| Resource::deserialize(doc).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn disposals_are_priced_at_the_sale_price() {
+        let yaml = r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  cost_basis: Fifo
+  accounts:
+    cash_account: assets:cash
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-02, commodity: PTT, price: 30, volume: 100}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {date: 2023-01-04, commodity: PTT, price: 34.25, volume: 40, commission: 10}
+"#;
+        let options = BuildOptions {
+            lot_costs: true,
+            ..Default::default()
+        };
+        let build = build(resources(yaml), &options);
+        assert!(build.errors.is_empty(), "{:?}", build.errors);
+
+        let body = HLedger {
+            declarations: false,
+        }
+        .body(&build.entries, &[]);
+
+        let positions: Vec<&str> = body
+            .lines()
+            .filter(|l| l.trim_start().starts_with("assets:positions"))
+            .map(|l| l.split_once("PTT").unwrap().1)
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                " 100 {THB 30} [2023-01-02] @ THB 30",
+                " -40 {THB 30} [2023-01-02] @ THB 34.25"
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_precision_leaves_lot_costs_out() {
+        let yaml = r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  cost_basis: Average
+  accounts:
+    cash_account: assets:cash
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-02, commodity: PTT, price: 10, volume: 1}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-03, commodity: PTT, price: 10.01, volume: 2}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {date: 2023-01-04, commodity: PTT, price: 11, volume: 1}
+---
+kind: Price
+spec: {date: 2023-01-05, commodity: PTT, price: 0.125, currency: USD}
+"#;
+        let resources = resources(yaml);
+        let prices = crate::journal::market_prices(&resources);
+        let portfolios: Vec<CashBalancePortfolio> = resources
+            .iter()
+            .filter_map(|r| match r {
+                Resource::CashBalancePortfolio(port) => Some(port.as_ref().clone()),
+                _ => None,
+            })
+            .collect();
+        let options = BuildOptions {
+            lot_costs: true,
+            ..Default::default()
+        };
+        let build = build(resources, &options);
+        assert!(build.errors.is_empty(), "{:?}", build.errors);
+        // the pool costs 30.02 for 3 units, a unit cost with no exact decimal
+        // form, and so is the gain on the unit sold
+        let unit_scales = build
+            .entries
+            .iter()
+            .flat_map(|e| &e.postings)
+            .filter_map(|p| p.cost.as_ref())
+            .map(|c| c.unit.0.scale());
+        assert!(unit_scales.max().unwrap() > 20);
+
+        let header = Renderer::header(
+            &HLedger { declarations: true },
+            &portfolios,
+            &build.entries,
+            &prices,
+        );
+
+        let commodities: Vec<&str> = header.lines().take_while(|l| !l.is_empty()).collect();
+        assert_eq!(
+            commodities,
+            vec![
+                "commodity PTT 1000",
+                "commodity THB 1000.00",
+                "commodity USD 1000.000"
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use super::{average, commodities, lots_summary, EntryRenderer};
+use crate::{
+    journal::{JournalEntry, Posting},
+    model::{port::CashBalancePortfolio, price::Price, Commodity},
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet};
+
+const DATE_FORMAT: &str = "%Y/%m/%d";
+
+/// ledger-cli syntax. Sold lots are valued at their sale price so that Ledger
+/// computes the gain itself, which leaves the profit or loss posting as a
+/// virtual one. Ledger does not infer an elided amount spanning several
+/// commodities the way hledger does, so those are written out.
+pub struct Ledger;
+
+impl EntryRenderer for Ledger {
+    fn header(
+        &self,
+        portfolios: &[CashBalancePortfolio],
+        entries: &[JournalEntry],
+        prices: &[Price],
+    ) -> String {
+        let mut lines: Vec<String> = commodities(entries, prices)
+            .into_iter()
+            .map(|c| format!("commodity {}", c.0))
+            .collect();
+        let mut declared = BTreeSet::new();
+        let accounts: Vec<String> = portfolios
+            .iter()
+            .flat_map(|port| port.accounts.all())
+            .filter(|a| declared.insert(*a))
+            .map(|a| format!("account {}", a.0))
+            .collect();
+        if !lines.is_empty() && !accounts.is_empty() {
+            lines.push("".into());
+        }
+        lines.extend(accounts);
+        lines.join("\n")
+    }
+
+    fn entry(&self, entry: &JournalEntry) -> String {
+        let mut lines = vec![format!(
+            "{} {}",
+            entry.date.0.format(DATE_FORMAT),
+            entry.description
+        )];
+        if let Some(lots) = &entry.inventory {
+            let (total_lot, avg_cost) = average(lots);
+            lines.push(format!("    ; avg: {total_lot} @{avg_cost}"));
+            lines.push(format!("    ; inventory: {}", lots_summary(lots)));
+        }
+
+        let postings = explicit_postings(entry);
+        let accounts: Vec<String> = postings
+            .iter()
+            .map(|p| match p.realized_gain {
+                true => format!("({})", p.account.0),
+                false => p.account.0.clone(),
+            })
+            .collect();
+        let max_account_len = accounts.iter().map(String::len).max().unwrap_or_default();
+        for (p, account) in postings.iter().zip(&accounts) {
+            let amount_str = p
+                .amount
+                .as_ref()
+                .map(|a| format!("{} {}", a.0 .0, a.1 .0))
+                .unwrap_or_default();
+            let cost_str = p
+                .cost
+                .as_ref()
+                .map(|c| {
+                    let note =
+                        c.id.as_ref()
+                            .map(|id| format!(" ({})", id.0))
+                            .unwrap_or_default();
+                    let price = match (&c.sold_at, &c.total) {
+                        (Some(sold_at), _) => format!("@ {} {}", c.currency.0, sold_at.0),
+                        (None, Some(total)) => format!("@@ {} {}", c.currency.0, total.0),
+                        (None, None) => format!("@ {} {}", c.currency.0, c.unit.0),
+                    };
+                    format!(
+                        " {{{} {}}} [{}]{note} {price}",
+                        c.currency.0,
+                        c.unit.0,
+                        c.date.0.format(DATE_FORMAT)
+                    )
+                })
+                .unwrap_or_default();
+            let assertion_str = match (&p.amount, &p.assertion) {
+                (Some((commodity, _)), Some(balance)) => {
+                    format!(" = {} {}", commodity.0, balance.0)
+                }
+                _ => "".into(),
+            };
+            let comment_str = p
+                .comment
+                .as_ref()
+                .map(|c| format!("  ; {c}"))
+                .unwrap_or_default();
+            let spaces = match amount_str.is_empty() {
+                true => "".into(),
+                false => " ".repeat(max_account_len + 4 - account.len()),
+            };
+            lines.push(format!(
+                "    {account}{spaces}{amount_str}{cost_str}{assertion_str}{comment_str}"
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn price(&self, price: &Price) -> String {
+        format!(
+            "P {} {} {} {}",
+            price.date.0.format(DATE_FORMAT),
+            price.commodity.0,
+            price.currency.0,
+            price.price.0
+        )
+    }
+
+    fn include(&self, path: &str) -> String {
+        format!("include {path}")
+    }
+}
+
+/// Postings with the single elided amount, if any, replaced by one posting per
+/// commodity left unbalanced by the others.
+fn explicit_postings(entry: &JournalEntry) -> Vec<Posting> {
+    let elided: Vec<usize> = entry
+        .postings
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.amount.is_none())
+        .map(|(idx, _)| idx)
+        .collect();
+    let [elided] = elided[..] else {
+        return entry.postings.clone();
+    };
+
+    let mut residuals: BTreeMap<Commodity, Decimal> = BTreeMap::new();
+    for p in entry.postings.iter().filter(|p| !p.realized_gain) {
+        if let Some((commodity, amount)) = weight(p) {
+            *residuals.entry(commodity).or_default() += amount;
+        }
+    }
+    let balancing: Vec<Posting> = residuals
+        .into_iter()
+        .filter(|(_, residual)| *residual != Decimal::ZERO)
+        .map(|(commodity, residual)| Posting {
+            amount: Some((commodity, (-residual).into())),
+            ..entry.postings[elided].clone()
+        })
+        .collect();
+    if balancing.is_empty() {
+        return entry.postings.clone();
+    }
+
+    let mut postings = entry.postings.clone();
+    postings.splice(elided..=elided, balancing);
+    postings
+}
+
+/// Amount a posting contributes to the balance of its transaction.
+fn weight(posting: &Posting) -> Option<(Commodity, Decimal)> {
+    let (commodity, amount) = posting.amount.as_ref()?;
+    match &posting.cost {
+        Some(cost) => {
+            let value = match (&cost.sold_at, &cost.total) {
+                (Some(sold_at), _) => sold_at.0 * amount.0,
+                (None, Some(total)) if amount.0.is_sign_negative() => -total.0,
+                (None, Some(total)) => total.0,
+                (None, None) => cost.unit.0 * amount.0,
+            };
+            Some((cost.currency.clone(), value))
+        }
+        None => Some((commodity.clone(), amount.0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::Resource,
+        journal::{build, AssertionOrder, BuildOptions},
+        render::Renderer,
+    };
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    const RESOURCES: &str = r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  accounts:
+    cash_account: assets:cash
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+    dividend_account: income:dividend
+---
+kind: Deposit
+spec:
+  port_id: port
+  detail: {date: 2023-01-02, amount: 10000}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-03, commodity: PTT, price: 30, volume: 100}
+---
+kind: Dividend
+spec:
+  port_id: port
+  detail: {ex_date: 2023-01-05, pay_date: 2023-02-10, commodity: PTT, per_share: 2}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {date: 2023-01-10, settlement_date: 2023-01-12, commodity: PTT, price: 35, volume: 40}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-11, commodity: PTT, price: 32, volume: 10}
+"#;
+
+    #[test]
+    fn assertions_follow_the_file_order() {
+        let resources: Vec<Resource> = serde_yaml::Deserializer::from_str(RESOURCES)
+            .map(|doc| Resource::deserialize(doc).unwrap())
+            .collect();
+        let options = BuildOptions {
+            balance_assertions: true,
+            lot_costs: true,
+            assertion_order: AssertionOrder::File,
+            ..Default::default()
+        };
+        let build = build(resources, &options);
+        assert!(build.errors.is_empty(), "{:?}", build.errors);
+        // the dividend is paid and the sale settled after the next buy
+        assert!(build.entries.windows(2).any(|w| w[0].date > w[1].date));
+
+        let journal = Ledger.body(&build.entries, &[]);
+
+        let mut balances: HashMap<(String, String), Decimal> = HashMap::new();
+        let mut assertions = 0;
+        for line in journal.lines().filter(|l| l.starts_with("    ")) {
+            let Some((account, rest)) = line.trim().split_once("  ") else {
+                continue;
+            };
+            let mut amount = rest.split_whitespace();
+            let (Some(commodity), Some(quantity)) = (amount.next(), amount.next()) else {
+                continue;
+            };
+            let balance = balances
+                .entry((account.into(), commodity.into()))
+                .or_default();
+            *balance += quantity.parse::<Decimal>().unwrap();
+            if let Some((_, asserted)) = rest.split_once(" = ") {
+                assert_eq!(asserted, format!("{commodity} {balance}"), "{line}");
+                assertions += 1;
+            }
+        }
+        assert_eq!(assertions, 8);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    inventory::Lot,
+    journal::{AssertionOrder, JournalEntry},
+    model::{port::CashBalancePortfolio, price::Price, Commodity},
+    output::Split,
+};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use std::collections::BTreeSet;
+
+pub mod beancount;
+pub mod data;
+pub mod hledger;
+pub mod ledger;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A journal written in the format of one accounting tool or data exchange format.
+pub trait Renderer {
+    /// Declarations that must precede the entries, empty when none are needed.
+    fn header(
+        &self,
+        _portfolios: &[CashBalancePortfolio],
+        _entries: &[JournalEntry],
+        _prices: &[Price],
+    ) -> String {
+        String::new()
+    }
+
+    /// Entries with `prices` placed among them.
+    fn body(&self, entries: &[JournalEntry], prices: &[Price]) -> String;
+
+    /// Prices alone, for a prices file kept next to the journal.
+    fn prices(&self, prices: &[Price]) -> String;
+
+    /// Directive pulling in another journal file, `None` for formats that have
+    /// no such thing.
+    fn include(&self, _path: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Text journal syntax, rendered one entry at a time.
+pub trait EntryRenderer {
+    /// Declarations that must precede the entries, empty when none are needed.
+    fn header(
+        &self,
+        _portfolios: &[CashBalancePortfolio],
+        _entries: &[JournalEntry],
+        _prices: &[Price],
+    ) -> String {
+        String::new()
+    }
+
+    fn entry(&self, entry: &JournalEntry) -> String;
+
+    fn price(&self, price: &Price) -> String;
+
+    fn include(&self, path: &str) -> String;
+}
+
+impl<T: EntryRenderer> Renderer for T {
+    fn header(
+        &self,
+        portfolios: &[CashBalancePortfolio],
+        entries: &[JournalEntry],
+        prices: &[Price],
+    ) -> String {
+        EntryRenderer::header(self, portfolios, entries, prices)
+    }
+
+    fn body(&self, entries: &[JournalEntry], prices: &[Price]) -> String {
+        let mut blocks = Vec::new();
+        let placed = place_prices(entries, prices);
+        for (e, prices) in entries.iter().zip(&placed) {
+            blocks.push(price_block(self, prices));
+            blocks.push(self.entry(e));
+        }
+        blocks.extend(placed.last().map(|prices| price_block(self, prices)));
+        blocks
+            .into_iter()
+            .filter(|b| !b.is_empty())
+            .map(|b| b + "\n\n")
+            .collect()
+    }
+
+    fn prices(&self, prices: &[Price]) -> String {
+        prices.iter().map(|p| self.price(p) + "\n").collect()
+    }
+
+    fn include(&self, path: &str) -> Option<String> {
+        Some(EntryRenderer::include(self, path))
+    }
+}
+
+fn price_block(renderer: &impl EntryRenderer, prices: &[&Price]) -> String {
+    prices
+        .iter()
+        .map(|p| renderer.price(p))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Prices to write before each entry, plus those left for after the last one.
+/// Entries are not strictly sorted by date, so a price goes before the first
+/// entry of its own date, or of a later date when there is none.
+fn place_prices<'a>(entries: &[JournalEntry], prices: &'a [Price]) -> Vec<Vec<&'a Price>> {
+    let mut placed: Vec<Vec<&Price>> = vec![Vec::new(); entries.len() + 1];
+    for p in prices {
+        let idx = entries
+            .iter()
+            .position(|e| e.date == p.date)
+            .or_else(|| entries.iter().position(|e| e.date > p.date))
+            .unwrap_or(entries.len());
+        placed[idx].push(p);
+    }
+    placed
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// hledger journal
+    #[default]
+    Hledger,
+    /// Beancount ledger
+    Beancount,
+    /// ledger-cli journal
+    Ledger,
+    /// JSON array of entries and prices
+    Json,
+    /// One JSON entry or price per line
+    Jsonl,
+    /// One CSV row per posting
+    Csv,
+}
+
+impl OutputFormat {
+    /// `declarations` only matters to hledger, the other text formats always
+    /// declare accounts and commodities.
+    pub fn renderer(&self, declarations: bool) -> Box<dyn Renderer> {
+        match self {
+            OutputFormat::Hledger => Box::new(hledger::HLedger { declarations }),
+            OutputFormat::Beancount => Box::new(beancount::Beancount),
+            OutputFormat::Ledger => Box::new(ledger::Ledger),
+            OutputFormat::Json => Box::new(data::Json),
+            OutputFormat::Jsonl => Box::new(data::JsonLines),
+            OutputFormat::Csv => Box::new(data::Csv),
+        }
+    }
+
+    /// Whether positions must carry their lot cost for the output to balance,
+    /// neither Beancount nor Ledger infers the conversion account posting of a
+    /// trade.
+    pub fn requires_lot_costs(&self) -> bool {
+        matches!(self, OutputFormat::Beancount | OutputFormat::Ledger)
+    }
+
+    /// Order the backend checks balance assertions in. Ledger follows the files,
+    /// which are read one part after the other when the journal is split.
+    pub fn assertion_order(&self, split: Option<Split>) -> AssertionOrder {
+        match (self, split) {
+            (OutputFormat::Ledger, Some(Split::Year)) => AssertionOrder::FilePerYear,
+            (OutputFormat::Ledger, _) => AssertionOrder::File,
+            _ => AssertionOrder::Date,
+        }
+    }
+}
+
+/// Total volume and average cost of `lots`, the average rounded for display.
+fn average(lots: &[Lot]) -> (Decimal, Decimal) {
+    let total_lot: Decimal = lots.iter().map(|lot| lot.volume.0).sum();
+    let avg_cost = if total_lot == Decimal::ZERO {
+        Decimal::ZERO
+    } else {
+        lots.iter().map(|lot| lot.cost.0).sum::<Decimal>() / total_lot
+    };
+    (total_lot, avg_cost.round_dp(6))
+}
+
+fn lots_summary(lots: &[Lot]) -> String {
+    lots.iter()
+        .map(|lot| match &lot.id {
+            Some(id) => format!("{} @{} ({})", lot.volume.0, lot.price.0, id.0),
+            None => format!("{} @{}", lot.volume.0, lot.price.0),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Every commodity used by postings, lot costs and prices, sorted.
+fn commodities<'a>(entries: &'a [JournalEntry], prices: &'a [Price]) -> BTreeSet<&'a Commodity> {
+    let mut commodities = BTreeSet::new();
+    for p in entries.iter().flat_map(|e| &e.postings) {
+        if let Some((commodity, _)) = &p.amount {
+            commodities.insert(commodity);
+        }
+        if let Some(cost) = &p.cost {
+            commodities.insert(&cost.currency);
+        }
+    }
+    for p in prices {
+        commodities.insert(&p.commodity);
+        commodities.insert(&p.currency);
+    }
+    commodities
+}
@@ -0,0 +1,239 @@
+use super::{Align, ReportFormat, Table};
+use crate::{
+    journal::Holding,
+    model::{Commodity, Date, LotId, PortId},
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Position of one commodity in one portfolio, summarized from its open lots.
+#[derive(Debug, Clone, Serialize)]
+pub struct HoldingRow {
+    pub port_id: PortId,
+    pub commodity: Commodity,
+    pub currency: Option<Commodity>,
+    pub volume: Decimal,
+    pub average_cost: Decimal,
+    pub total_cost: Decimal,
+    pub lots: Vec<LotRow>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LotRow {
+    pub date: Date,
+    pub id: Option<LotId>,
+    pub volume: Decimal,
+    pub price: Decimal,
+    pub cost: Decimal,
+}
+
+pub fn holdings(holdings: &[Holding]) -> Vec<HoldingRow> {
+    holdings
+        .iter()
+        .map(|h| {
+            let lots: Vec<LotRow> = h
+                .lots
+                .iter()
+                .map(|lot| LotRow {
+                    date: lot.date,
+                    id: lot.id.clone(),
+                    volume: lot.volume.0,
+                    price: lot.price.0,
+                    cost: lot.cost.0,
+                })
+                .collect();
+            let volume: Decimal = lots.iter().map(|lot| lot.volume).sum();
+            let total_cost: Decimal = lots.iter().map(|lot| lot.cost).sum();
+            let average_cost = if volume == Decimal::ZERO {
+                Decimal::ZERO
+            } else {
+                (total_cost / volume).round_dp(6)
+            };
+            HoldingRow {
+                port_id: h.port_id.clone(),
+                commodity: h.commodity.clone(),
+                currency: h.lots.first().map(|lot| lot.currency.clone()),
+                volume,
+                average_cost,
+                total_cost,
+                lots,
+            }
+        })
+        .collect()
+}
+
+pub fn render(rows: &[HoldingRow], format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Table => render_table(rows),
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(rows).expect("holdings are always serializable") + "\n"
+        }
+    }
+}
+
+/// One summary line per holding followed by one indented line per open lot.
+fn render_table(rows: &[HoldingRow]) -> String {
+    let mut table = Table::new(&[
+        ("PORTFOLIO", Align::Left),
+        ("COMMODITY", Align::Left),
+        ("LOT", Align::Left),
+        ("VOLUME", Align::Right),
+        ("PRICE", Align::Right),
+        ("COST", Align::Right),
+        ("CURRENCY", Align::Left),
+    ]);
+    for row in rows {
+        let currency = row
+            .currency
+            .as_ref()
+            .map(|c| c.0.clone())
+            .unwrap_or_default();
+        table.push(vec![
+            row.port_id.0.clone(),
+            row.commodity.0.clone(),
+            "".into(),
+            row.volume.to_string(),
+            row.average_cost.to_string(),
+            row.total_cost.to_string(),
+            currency,
+        ]);
+        for lot in &row.lots {
+            let label = match &lot.id {
+                Some(id) => format!("{} ({})", lot.date, id.0),
+                None => lot.date.to_string(),
+            };
+            table.push(vec![
+                "".into(),
+                "".into(),
+                label,
+                lot.volume.to_string(),
+                lot.price.to_string(),
+                lot.cost.to_string(),
+                "".into(),
+            ]);
+        }
+    }
+    table.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::Resource,
+        journal::{build, BuildOptions},
+    };
+    use serde::Deserialize;
+
+    const RESOURCES: &str = r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  cost_basis: Fifo
+  accounts:
+    cash_account: assets:cash
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-02, commodity: PTT, price: 30, volume: 100, lot_id: first}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-04, commodity: PTT, price: 36.5, volume: 50}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {date: 2023-01-05, commodity: PTT, price: 40, volume: 40}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {date: 2023-01-10, commodity: PTT, price: 50, volume: 10}
+"#;
+
+    fn rows(as_of: &str) -> Vec<HoldingRow> {
+        let resources: Vec<Resource> = serde_yaml::Deserializer::from_str(RESOURCES)
+            .map(|doc| Resource::deserialize(doc).unwrap())
+            .collect();
+        let options = BuildOptions {
+            as_of: Some(as_of.parse().unwrap()),
+            ..Default::default()
+        };
+        let build = build(resources, &options);
+        assert!(build.errors.is_empty(), "{:?}", build.errors);
+        holdings(&build.holdings)
+    }
+
+    #[test]
+    fn holdings_include_transactions_up_to_the_as_of_date() {
+        let volumes = |as_of: &str| -> Vec<Decimal> {
+            rows(as_of)
+                .iter()
+                .flat_map(|row| row.lots.iter().map(|lot| lot.volume))
+                .collect()
+        };
+
+        assert_eq!(volumes("2023-01-04"), vec![100.into(), 50.into()]);
+        assert_eq!(volumes("2023-01-05"), vec![60.into(), 50.into()]);
+        assert_eq!(volumes("2023-01-10"), vec![60.into(), 50.into(), 10.into()]);
+    }
+
+    #[test]
+    fn average_cost_is_the_total_cost_per_unit_held() {
+        let rows = rows("2023-01-05");
+
+        // 60 at 30 and 50 at 36.5 left after the sale
+        assert_eq!(rows[0].volume, Decimal::from(110));
+        assert_eq!(rows[0].total_cost, Decimal::from(3625));
+        assert_eq!(rows[0].average_cost, "32.954545".parse().unwrap());
+    }
+
+    #[test]
+    fn table_lists_each_lot_under_its_holding() {
+        let table = render(&rows("2023-01-05"), ReportFormat::Table);
+
+        assert_eq!(
+            table,
+            "\
+PORTFOLIO  COMMODITY  LOT                 VOLUME      PRICE    COST  CURRENCY
+port       PTT                               110  32.954545  3625.0  THB
+                      2023-01-02 (first)      60         30    1800
+                      2023-01-04              50       36.5  1825.0
+"
+        );
+    }
+
+    #[test]
+    fn json_has_one_object_per_holding_with_its_lots() {
+        let json = render(&rows("2023-01-05"), ReportFormat::Json);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([{
+                "port_id": "port",
+                "commodity": "PTT",
+                "currency": "THB",
+                "volume": "110",
+                "average_cost": "32.954545",
+                "total_cost": "3625.0",
+                "lots": [
+                    {"date": "2023-01-02", "id": "first", "volume": "60", "price": "30", "cost": "1800"},
+                    {"date": "2023-01-04", "id": null, "volume": "50", "price": "36.5", "cost": "1825.0"},
+                ],
+            }])
+        );
+    }
+}
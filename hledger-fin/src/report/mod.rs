@@ -0,0 +1,77 @@
+use clap::ValueEnum;
+use std::fmt;
+
+pub mod holdings;
+pub mod realized;
+pub mod unrealized;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Aligned text table
+    #[default]
+    Table,
+    /// JSON document
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Plain text table with columns padded to their widest cell.
+pub struct Table {
+    columns: Vec<(&'static str, Align)>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: &[(&'static str, Align)]) -> Self {
+        Self {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, (title, _))| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|cell| cell.chars().count())
+                    .chain([title.len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let header: Vec<String> = self.columns.iter().map(|(t, _)| t.to_string()).collect();
+        for row in [&header].into_iter().chain(&self.rows) {
+            let cells: Vec<String> = self
+                .columns
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, ((_, align), width))| {
+                    let cell = row.get(i).map(String::as_str).unwrap_or_default();
+                    match align {
+                        Align::Left => format!("{cell:<width$}"),
+                        Align::Right => format!("{cell:>width$}"),
+                    }
+                })
+                .collect();
+            writeln!(f, "{}", cells.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use super::{Align, ReportFormat, Table};
+use crate::{
+    journal::Disposal,
+    model::{Commodity, Date, LotId, PortId},
+};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Period {
+    #[default]
+    Year,
+    Month,
+}
+
+impl Period {
+    fn key(&self, date: &Date) -> String {
+        match self {
+            Period::Year => date.0.format("%Y").to_string(),
+            Period::Month => date.0.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// One lot matched against a sale.
+#[derive(Debug, Clone, Serialize)]
+pub struct RealizedLot {
+    pub port_id: PortId,
+    pub commodity: Commodity,
+    pub currency: Commodity,
+    pub buy_date: Date,
+    pub sell_date: Date,
+    pub lot_id: Option<LotId>,
+    pub volume: Decimal,
+    pub cost: Decimal,
+    pub proceeds: Decimal,
+    pub fees: Decimal,
+    pub gain: Decimal,
+    /// Not known under the average cost basis, which pools every buy into a lot
+    /// dated by the latest one
+    pub holding_days: Option<i64>,
+}
+
+/// Sums over the realized lots sharing `group`. Amounts in different currencies
+/// are never added together.
+#[derive(Debug, Clone, Serialize)]
+pub struct RealizedTotal {
+    pub group: String,
+    pub currency: Commodity,
+    pub cost: Decimal,
+    pub proceeds: Decimal,
+    pub fees: Decimal,
+    pub gain: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RealizedReport {
+    pub lots: Vec<RealizedLot>,
+    pub by_commodity: Vec<RealizedTotal>,
+    pub by_portfolio: Vec<RealizedTotal>,
+    pub by_period: Vec<RealizedTotal>,
+}
+
+/// Realized gains of the sales dated within `from..=to`.
+pub fn realized(
+    disposals: &[Disposal],
+    from: Option<Date>,
+    to: Option<Date>,
+    period: Period,
+) -> RealizedReport {
+    let mut lots: Vec<RealizedLot> = disposals
+        .iter()
+        .filter(|d| from.is_none_or(|from| d.date >= from))
+        .filter(|d| to.is_none_or(|to| d.date <= to))
+        .map(|d| RealizedLot {
+            port_id: d.port_id.clone(),
+            commodity: d.commodity.clone(),
+            currency: d.currency.clone(),
+            buy_date: d.lot.date,
+            sell_date: d.date,
+            lot_id: d.lot.id.clone(),
+            volume: d.lot.volume.0,
+            cost: d.lot.cost.0,
+            proceeds: d.proceeds,
+            fees: d.fees,
+            gain: d.gain,
+            holding_days: (!d.pooled).then(|| (d.date.0 - d.lot.date.0).num_days()),
+        })
+        .collect();
+    lots.sort_by(|a, b| {
+        (&a.sell_date, &a.port_id, &a.commodity).cmp(&(&b.sell_date, &b.port_id, &b.commodity))
+    });
+
+    RealizedReport {
+        by_commodity: totals(&lots, |lot| lot.commodity.0.clone()),
+        by_portfolio: totals(&lots, |lot| lot.port_id.0.clone()),
+        by_period: totals(&lots, |lot| period.key(&lot.sell_date)),
+        lots,
+    }
+}
+
+fn totals(lots: &[RealizedLot], group: impl Fn(&RealizedLot) -> String) -> Vec<RealizedTotal> {
+    let mut totals: BTreeMap<(String, Commodity), RealizedTotal> = BTreeMap::new();
+    for lot in lots {
+        let key = (group(lot), lot.currency.clone());
+        let total = totals.entry(key.clone()).or_insert_with(|| RealizedTotal {
+            group: key.0,
+            currency: key.1,
+            cost: Decimal::ZERO,
+            proceeds: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            gain: Decimal::ZERO,
+        });
+        total.cost += lot.cost;
+        total.proceeds += lot.proceeds;
+        total.fees += lot.fees;
+        total.gain += lot.gain;
+    }
+    totals.into_values().collect()
+}
+
+pub fn render(report: &RealizedReport, format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Table => render_table(report),
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(report).expect("realized gains are always serializable")
+                + "\n"
+        }
+    }
+}
+
+fn render_table(report: &RealizedReport) -> String {
+    let mut lots = Table::new(&[
+        ("PORTFOLIO", Align::Left),
+        ("COMMODITY", Align::Left),
+        ("BOUGHT", Align::Left),
+        ("SOLD", Align::Left),
+        ("DAYS", Align::Right),
+        ("VOLUME", Align::Right),
+        ("COST", Align::Right),
+        ("PROCEEDS", Align::Right),
+        ("FEES", Align::Right),
+        ("GAIN", Align::Right),
+        ("CURRENCY", Align::Left),
+    ]);
+    for lot in &report.lots {
+        let bought = match &lot.lot_id {
+            Some(id) => format!("{} ({})", lot.buy_date, id.0),
+            None => lot.buy_date.to_string(),
+        };
+        lots.push(vec![
+            lot.port_id.0.clone(),
+            lot.commodity.0.clone(),
+            bought,
+            lot.sell_date.to_string(),
+            lot.holding_days
+                .map_or_else(|| "-".into(), |days| days.to_string()),
+            lot.volume.to_string(),
+            lot.cost.to_string(),
+            lot.proceeds.to_string(),
+            lot.fees.to_string(),
+            lot.gain.to_string(),
+            lot.currency.0.clone(),
+        ]);
+    }
+
+    let sections = [
+        ("COMMODITY", &report.by_commodity),
+        ("PORTFOLIO", &report.by_portfolio),
+        ("PERIOD", &report.by_period),
+    ];
+    let mut output = lots.to_string();
+    for (title, totals) in sections {
+        let mut table = Table::new(&[
+            (title, Align::Left),
+            ("COST", Align::Right),
+            ("PROCEEDS", Align::Right),
+            ("FEES", Align::Right),
+            ("GAIN", Align::Right),
+            ("CURRENCY", Align::Left),
+        ]);
+        for total in totals {
+            table.push(vec![
+                total.group.clone(),
+                total.cost.to_string(),
+                total.proceeds.to_string(),
+                total.fees.to_string(),
+                total.gain.to_string(),
+                total.currency.0.clone(),
+            ]);
+        }
+        output.push('\n');
+        output.push_str(&table.to_string());
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inventory::Lot, model::CommodityAmount};
+
+    fn disposal(pooled: bool) -> Disposal {
+        Disposal {
+            port_id: PortId("port".into()),
+            commodity: Commodity("PTT".into()),
+            currency: Commodity("THB".into()),
+            date: "2023-03-01".parse().unwrap(),
+            lot: Lot {
+                date: "2023-01-01".parse().unwrap(),
+                price: Decimal::from(10).into(),
+                volume: CommodityAmount(100.into()),
+                currency: Commodity("THB".into()),
+                id: None,
+                cost: CommodityAmount(1000.into()),
+            },
+            pooled,
+            proceeds: 1200.into(),
+            fees: Decimal::ZERO,
+            gain: 200.into(),
+        }
+    }
+
+    #[test]
+    fn holding_days_are_unknown_for_pooled_lots() {
+        let report = realized(&[disposal(false), disposal(true)], None, None, Period::Year);
+
+        let days: Vec<Option<i64>> = report.lots.iter().map(|l| l.holding_days).collect();
+        assert_eq!(days, vec![Some(59), None]);
+    }
+}
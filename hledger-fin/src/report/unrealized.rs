@@ -0,0 +1,199 @@
+use super::{Align, ReportFormat, Table};
+use crate::{
+    journal::Holding,
+    model::{price::Price, Commodity, Date, LotId, PortId},
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Open lot valued at the latest market price on or before the valuation date.
+/// Valuation fields are empty when no price is known in the lot currency.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnrealizedLot {
+    pub port_id: PortId,
+    pub commodity: Commodity,
+    pub currency: Commodity,
+    pub date: Date,
+    pub lot_id: Option<LotId>,
+    pub volume: Decimal,
+    pub cost_price: Decimal,
+    pub cost: Decimal,
+    pub market_price: Option<Decimal>,
+    pub price_date: Option<Date>,
+    pub value: Option<Decimal>,
+    pub gain: Option<Decimal>,
+}
+
+/// Lots of one commodity in one portfolio. Valuation fields are empty when any
+/// of its lots could not be valued.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnrealizedTotal {
+    pub port_id: PortId,
+    pub commodity: Commodity,
+    pub currency: Commodity,
+    pub volume: Decimal,
+    pub cost: Decimal,
+    pub value: Option<Decimal>,
+    pub gain: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnrealizedReport {
+    pub as_of: Option<Date>,
+    pub lots: Vec<UnrealizedLot>,
+    pub by_commodity: Vec<UnrealizedTotal>,
+}
+
+/// Price history per commodity and quote currency. A later price of the same
+/// day replaces an earlier one.
+struct PriceHistory(HashMap<(Commodity, Commodity), BTreeMap<Date, Decimal>>);
+
+impl PriceHistory {
+    fn new(prices: &[Price]) -> Self {
+        let mut history: HashMap<(Commodity, Commodity), BTreeMap<Date, Decimal>> = HashMap::new();
+        for p in prices {
+            history
+                .entry((p.commodity.clone(), p.currency.clone()))
+                .or_default()
+                .insert(p.date, p.price.0);
+        }
+        Self(history)
+    }
+
+    fn latest(
+        &self,
+        commodity: &Commodity,
+        currency: &Commodity,
+        as_of: Option<Date>,
+    ) -> Option<(Date, Decimal)> {
+        let prices = self.0.get(&(commodity.clone(), currency.clone()))?;
+        let latest = match as_of {
+            Some(as_of) => prices.range(..=as_of).next_back(),
+            None => prices.iter().next_back(),
+        };
+        latest.map(|(date, price)| (*date, *price))
+    }
+}
+
+/// Value `holdings` at the latest price on or before `as_of`, or the latest
+/// price known when no date is given. Prices are not converted between
+/// currencies, a lot is only valued by prices quoted in its own currency.
+pub fn unrealized(holdings: &[Holding], prices: &[Price], as_of: Option<Date>) -> UnrealizedReport {
+    let history = PriceHistory::new(prices);
+    let mut lots = Vec::new();
+    let mut by_commodity = Vec::new();
+    for h in holdings {
+        let holding_lots: Vec<UnrealizedLot> = h
+            .lots
+            .iter()
+            .map(|lot| {
+                let cost = lot.cost.0;
+                let market = history.latest(&h.commodity, &lot.currency, as_of);
+                let value = market.map(|(_, price)| lot.volume.0 * price);
+                UnrealizedLot {
+                    port_id: h.port_id.clone(),
+                    commodity: h.commodity.clone(),
+                    currency: lot.currency.clone(),
+                    date: lot.date,
+                    lot_id: lot.id.clone(),
+                    volume: lot.volume.0,
+                    cost_price: lot.price.0,
+                    cost,
+                    market_price: market.map(|(_, price)| price),
+                    price_date: market.map(|(date, _)| date),
+                    value,
+                    gain: value.map(|value| value - cost),
+                }
+            })
+            .collect();
+        if let Some(first) = holding_lots.first() {
+            let cost: Decimal = holding_lots.iter().map(|lot| lot.cost).sum();
+            let value: Option<Decimal> = holding_lots.iter().map(|lot| lot.value).sum();
+            by_commodity.push(UnrealizedTotal {
+                port_id: h.port_id.clone(),
+                commodity: h.commodity.clone(),
+                currency: first.currency.clone(),
+                volume: holding_lots.iter().map(|lot| lot.volume).sum(),
+                cost,
+                value,
+                gain: value.map(|value| value - cost),
+            });
+        }
+        lots.extend(holding_lots);
+    }
+    UnrealizedReport {
+        as_of,
+        lots,
+        by_commodity,
+    }
+}
+
+pub fn render(report: &UnrealizedReport, format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Table => render_table(report),
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(report).expect("unrealized gains are always serializable")
+                + "\n"
+        }
+    }
+}
+
+fn render_table(report: &UnrealizedReport) -> String {
+    let optional = |value: &Option<Decimal>| value.map_or("-".into(), |v| v.to_string());
+
+    let mut lots = Table::new(&[
+        ("PORTFOLIO", Align::Left),
+        ("COMMODITY", Align::Left),
+        ("LOT", Align::Left),
+        ("VOLUME", Align::Right),
+        ("COST PRICE", Align::Right),
+        ("COST", Align::Right),
+        ("PRICE", Align::Right),
+        ("PRICE DATE", Align::Left),
+        ("VALUE", Align::Right),
+        ("GAIN", Align::Right),
+        ("CURRENCY", Align::Left),
+    ]);
+    for lot in &report.lots {
+        let label = match &lot.lot_id {
+            Some(id) => format!("{} ({})", lot.date, id.0),
+            None => lot.date.to_string(),
+        };
+        lots.push(vec![
+            lot.port_id.0.clone(),
+            lot.commodity.0.clone(),
+            label,
+            lot.volume.to_string(),
+            lot.cost_price.to_string(),
+            lot.cost.to_string(),
+            optional(&lot.market_price),
+            lot.price_date.map_or("-".into(), |d| d.to_string()),
+            optional(&lot.value),
+            optional(&lot.gain),
+            lot.currency.0.clone(),
+        ]);
+    }
+
+    let mut totals = Table::new(&[
+        ("PORTFOLIO", Align::Left),
+        ("COMMODITY", Align::Left),
+        ("VOLUME", Align::Right),
+        ("COST", Align::Right),
+        ("VALUE", Align::Right),
+        ("GAIN", Align::Right),
+        ("CURRENCY", Align::Left),
+    ]);
+    for total in &report.by_commodity {
+        totals.push(vec![
+            total.port_id.0.clone(),
+            total.commodity.0.clone(),
+            total.volume.to_string(),
+            total.cost.to_string(),
+            optional(&total.value),
+            optional(&total.gain),
+            total.currency.0.clone(),
+        ]);
+    }
+    format!("{lots}\n{totals}")
+}
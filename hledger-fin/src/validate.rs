@@ -0,0 +1,472 @@
+use crate::{
+    input::Resource,
+    journal::{self, JournalEntry},
+    model::{port::CashBalancePortfolio, Account, Commodity, Date, PortId},
+};
+use rust_decimal::Decimal;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    DuplicatePortfolio,
+    OrphanTransaction,
+    InvalidAmount,
+    Journal,
+    NegativeCash,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::DuplicatePortfolio => "duplicate-portfolio",
+            Rule::OrphanTransaction => "orphan-transaction",
+            Rule::InvalidAmount => "invalid-amount",
+            Rule::Journal => "journal",
+            Rule::NegativeCash => "negative-cash",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.rule, self.message)
+    }
+}
+
+/// Semantic checks over parsed resources, for mistakes that `journal::build`
+/// would otherwise tolerate silently.
+pub fn validate(resources: &[Resource]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let mut portfolios: HashMap<&PortId, &CashBalancePortfolio> = HashMap::new();
+    for r in resources {
+        if let Resource::CashBalancePortfolio(port) = r {
+            match portfolios.entry(&port.port_id) {
+                Entry::Vacant(e) => {
+                    e.insert(port);
+                }
+                Entry::Occupied(_) => findings.push(Finding {
+                    rule: Rule::DuplicatePortfolio,
+                    message: format!(
+                        "portfolio {} is defined more than once, only the first one is used",
+                        port.port_id.0
+                    ),
+                }),
+            }
+        }
+    }
+
+    for r in resources {
+        check_resource(r, &portfolios, &mut findings);
+    }
+
+    let build = journal::build(resources.to_vec(), &journal::BuildOptions::default());
+    findings.extend(build.errors.into_iter().map(|e| Finding {
+        rule: Rule::Journal,
+        message: e.to_string(),
+    }));
+    check_cash(&build.entries, &portfolios, &mut findings);
+
+    findings
+}
+
+/// Amounts of a resource that must be above zero, and those that must not be below it.
+#[derive(Default)]
+struct Amounts {
+    positive: Vec<(&'static str, Decimal)>,
+    non_negative: Vec<(&'static str, Decimal)>,
+}
+
+impl Amounts {
+    fn positive(&mut self, field: &'static str, value: Option<Decimal>) -> &mut Self {
+        if let Some(value) = value {
+            self.positive.push((field, value));
+        }
+        self
+    }
+
+    fn non_negative(&mut self, field: &'static str, value: Option<Decimal>) -> &mut Self {
+        if let Some(value) = value {
+            self.non_negative.push((field, value));
+        }
+        self
+    }
+}
+
+fn check_resource(
+    resource: &Resource,
+    portfolios: &HashMap<&PortId, &CashBalancePortfolio>,
+    findings: &mut Vec<Finding>,
+) {
+    let mut amounts = Amounts::default();
+    let (port_ids, context): (Vec<&PortId>, String) = match resource {
+        Resource::CashBalancePortfolio(_) | Resource::Include(_) => return,
+        Resource::Deposit(i) => {
+            amounts.positive("amount", Some(i.detail.amount.0));
+            (vec![&i.port_id], context("Deposit", &i.detail.date, None))
+        }
+        Resource::Withdraw(i) => {
+            amounts.positive("amount", Some(i.detail.amount.0));
+            (vec![&i.port_id], context("Withdraw", &i.detail.date, None))
+        }
+        Resource::Buy(i) => {
+            let t = &i.detail;
+            amounts
+                .positive("volume", Some(t.volume.0))
+                .non_negative("price", Some(t.price.0))
+                .non_negative("commission", t.commission.as_ref().map(|c| c.0))
+                .non_negative("vat", t.vat.as_ref().map(|v| v.0));
+            let ctx = context("Buy", &t.date, Some(&t.commodity));
+            (vec![&i.port_id], ctx)
+        }
+        Resource::Sell(i) => {
+            let t = &i.detail;
+            amounts
+                .positive("volume", Some(t.volume.0))
+                .non_negative("price", Some(t.price.0))
+                .non_negative("commission", t.commission.as_ref().map(|c| c.0))
+                .non_negative("vat", t.vat.as_ref().map(|v| v.0));
+            for lot in t.lots.iter().flatten() {
+                amounts.positive("lots.volume", Some(lot.volume.0));
+            }
+            let ctx = context("Sell", &t.date, Some(&t.commodity));
+            (vec![&i.port_id], ctx)
+        }
+        Resource::Interest(i) => {
+            amounts.positive("amount", Some(i.detail.amount.0));
+            (vec![&i.port_id], context("Interest", &i.detail.date, None))
+        }
+        Resource::Dividend(i) => {
+            let t = &i.detail;
+            amounts
+                .positive("per_share", t.per_share.as_ref().map(|p| p.0))
+                .positive("amount", t.amount.as_ref().map(|a| a.0))
+                .non_negative("withholding_tax", t.withholding_tax.as_ref().map(|w| w.0));
+            let ctx = context("Dividend", &t.ex_date, Some(&t.commodity));
+            (vec![&i.port_id], ctx)
+        }
+        Resource::Split(i) => {
+            let t = &i.detail;
+            amounts
+                .positive("from", Some(t.from))
+                .positive("to", Some(t.to));
+            (
+                vec![&i.port_id],
+                context("Split", &t.date, Some(&t.commodity)),
+            )
+        }
+        Resource::StockDividend(i) => {
+            let t = &i.detail;
+            amounts
+                .positive("from", Some(t.from))
+                .positive("to", Some(t.to))
+                .non_negative("price", t.price.as_ref().map(|p| p.0))
+                .non_negative("cash_in_lieu", t.cash_in_lieu.as_ref().map(|c| c.0));
+            let ctx = context("StockDividend", &t.date, Some(&t.commodity));
+            (vec![&i.port_id], ctx)
+        }
+        Resource::FxConversion(i) => {
+            let t = &i.detail;
+            amounts
+                .positive("from_amount", Some(t.from_amount.0))
+                .positive("rate", Some(t.rate.0));
+            (vec![&i.port_id], context("FxConversion", &t.date, None))
+        }
+        Resource::TransferIn(i) => {
+            let t = &i.detail;
+            for lot in &t.lots {
+                amounts
+                    .positive("lots.volume", Some(lot.volume.0))
+                    .non_negative("lots.price", Some(lot.price.0));
+            }
+            let ctx = context("TransferIn", &t.date, Some(&t.commodity));
+            (vec![&i.port_id], ctx)
+        }
+        Resource::TransferOut(i) => {
+            let t = &i.detail;
+            amounts.positive("volume", Some(t.volume.0));
+            for lot in t.lots.iter().flatten() {
+                amounts.positive("lots.volume", Some(lot.volume.0));
+            }
+            let ctx = context("TransferOut", &t.date, Some(&t.commodity));
+            (vec![&i.port_id], ctx)
+        }
+        Resource::Price(p) => {
+            amounts.positive("price", Some(p.price.0));
+            (vec![], context("Price", &p.date, Some(&p.commodity)))
+        }
+        Resource::PortfolioTransfer(t) => {
+            amounts.positive("cash", t.cash.as_ref().map(|c| c.0));
+            if let Some(position) = &t.position {
+                amounts.positive("position.volume", Some(position.volume.0));
+                for lot in position.lots.iter().flatten() {
+                    amounts.positive("position.lots.volume", Some(lot.volume.0));
+                }
+            }
+            let commodity = t.position.as_ref().map(|p| &p.commodity);
+            (
+                vec![&t.from, &t.to],
+                context("PortfolioTransfer", &t.date, commodity),
+            )
+        }
+    };
+
+    for port_id in port_ids {
+        if !portfolios.contains_key(port_id) {
+            findings.push(Finding {
+                rule: Rule::OrphanTransaction,
+                message: format!("{context}: portfolio {} is not defined", port_id.0),
+            });
+        }
+    }
+    for (field, value) in amounts.positive {
+        if value <= Decimal::ZERO {
+            findings.push(Finding {
+                rule: Rule::InvalidAmount,
+                message: format!("{context}: {field} must be positive, got {value}"),
+            });
+        }
+    }
+    for (field, value) in amounts.non_negative {
+        if value < Decimal::ZERO {
+            findings.push(Finding {
+                rule: Rule::InvalidAmount,
+                message: format!("{context}: {field} must not be negative, got {value}"),
+            });
+        }
+    }
+}
+
+fn context(kind: &str, date: &Date, commodity: Option<&Commodity>) -> String {
+    match commodity {
+        Some(commodity) => format!("{kind} {} on {date}", commodity.0),
+        None => format!("{kind} on {date}"),
+    }
+}
+
+/// Replay postings to cash accounts in date order and report every time a cash
+/// balance drops below zero at the end of a day.
+fn check_cash(
+    entries: &[JournalEntry],
+    portfolios: &HashMap<&PortId, &CashBalancePortfolio>,
+    findings: &mut Vec<Finding>,
+) {
+    let cash_accounts: HashMap<&Account, &PortId> = portfolios
+        .iter()
+        .map(|(port_id, port)| (&port.accounts.cash_account, *port_id))
+        .collect();
+
+    let mut sorted_entries: Vec<&JournalEntry> = entries.iter().collect();
+    sorted_entries.sort_by_key(|e| e.date);
+
+    let mut balances: HashMap<(&Account, &Commodity), Decimal> = HashMap::new();
+    let mut negative: HashSet<(&Account, &Commodity)> = HashSet::new();
+    let mut idx = 0;
+    while idx < sorted_entries.len() {
+        let date = sorted_entries[idx].date;
+        while idx < sorted_entries.len() && sorted_entries[idx].date == date {
+            for p in &sorted_entries[idx].postings {
+                if let (true, Some((commodity, amount))) =
+                    (cash_accounts.contains_key(&p.account), &p.amount)
+                {
+                    *balances.entry((&p.account, commodity)).or_default() += amount.0;
+                }
+            }
+            idx += 1;
+        }
+
+        let mut keys: Vec<&(&Account, &Commodity)> = balances.keys().collect();
+        keys.sort();
+        for key in keys {
+            let balance = balances[key];
+            if balance < Decimal::ZERO && !negative.contains(key) {
+                findings.push(Finding {
+                    rule: Rule::NegativeCash,
+                    message: format!(
+                        "portfolio {}: {} {} goes negative to {balance} on {date}",
+                        cash_accounts[key.0].0, key.0 .0, key.1 .0
+                    ),
+                });
+            }
+        }
+        negative = balances
+            .iter()
+            .filter(|(_, balance)| **balance < Decimal::ZERO)
+            .map(|(key, _)| *key)
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    fn portfolio(cash_account: &str) -> String {
+        format!(
+            r#"
+kind: CashBalancePortfolio
+spec:
+  port_id: port
+  base_currency: THB
+  accounts:
+    cash_account: {cash_account}
+    cash_ar_account: assets:cash_ar
+    position_account: assets:positions
+    net_investment_account: equity:net_invest
+    conversion_account: equity:conversion
+    commission_account: expenses:commission
+    vat_account: expenses:vat
+    protfit_loss_account: income:profit_loss
+    interest_account: income:interest
+"#
+        )
+    }
+
+    /// Findings of the `---` separated YAML documents, as printed.
+    fn findings(yaml: &str) -> Vec<String> {
+        let resources: Vec<Resource> = serde_yaml::Deserializer::from_str(yaml)
+            .map(|doc| Resource::deserialize(doc).unwrap())
+            .collect();
+        validate(&resources).iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn duplicate_portfolio_is_checked_against_the_first_definition() {
+        let yaml = format!(
+            r#"{}
+---
+{}
+---
+kind: Withdraw
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, amount: 100}}
+"#,
+            portfolio("assets:first"),
+            portfolio("assets:second")
+        );
+        assert_eq!(
+            findings(&yaml),
+            vec![
+                "[duplicate-portfolio] portfolio port is defined more than once, only the first one is used",
+                "[negative-cash] portfolio port: assets:first THB goes negative to -100 on 2023-01-02",
+            ]
+        );
+    }
+
+    #[test]
+    fn transaction_of_an_undefined_portfolio_is_an_orphan() {
+        let yaml = format!(
+            r#"{}
+---
+kind: Deposit
+spec:
+  port_id: other
+  detail: {{date: 2023-01-02, amount: 100}}
+---
+kind: PortfolioTransfer
+spec: {{date: 2023-01-03, from: port, to: missing, cash: 10}}
+"#,
+            portfolio("assets:cash")
+        );
+
+        assert_eq!(
+            findings(&yaml),
+            vec![
+                "[orphan-transaction] Deposit on 2023-01-02: portfolio other is not defined",
+                "[orphan-transaction] PortfolioTransfer on 2023-01-03: portfolio missing is not defined",
+                "[journal] portfolio missing on 2023-01-03: no such portfolio",
+            ]
+        );
+    }
+
+    #[test]
+    fn amounts_out_of_range_are_invalid() {
+        let yaml = format!(
+            r#"{}
+---
+kind: Deposit
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, amount: 0}}
+---
+kind: Deposit
+spec:
+  port_id: port
+  detail: {{date: 2023-01-02, amount: 1000}}
+---
+kind: Buy
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: PTT, price: 30, volume: 10, commission: -5}}
+"#,
+            portfolio("assets:cash")
+        );
+
+        assert_eq!(
+            findings(&yaml),
+            vec![
+                "[invalid-amount] Deposit on 2023-01-02: amount must be positive, got 0",
+                "[invalid-amount] Buy PTT on 2023-01-03: commission must not be negative, got -5",
+            ]
+        );
+    }
+
+    #[test]
+    fn journal_errors_are_findings() {
+        let yaml = format!(
+            r#"{}
+---
+kind: Sell
+spec:
+  port_id: port
+  detail: {{date: 2023-01-03, commodity: PTT, price: 30, volume: 10}}
+"#,
+            portfolio("assets:cash")
+        );
+
+        assert_eq!(
+            findings(&yaml),
+            vec!["[journal] portfolio port, PTT on 2023-01-03: unable to take 10 out of inventory, only 0 available"]
+        );
+    }
+
+    #[test]
+    fn negative_cash_is_reported_each_time_it_goes_negative_at_the_end_of_a_day() {
+        let cash = |kind: &str, date: &str, amount: u32| {
+            format!(
+                "---\nkind: {kind}\nspec:\n  port_id: port\n  detail: {{date: {date}, amount: {amount}}}\n"
+            )
+        };
+        let yaml = [
+            portfolio("assets:cash"),
+            cash("Deposit", "2023-01-02", 100),
+            cash("Withdraw", "2023-01-03", 150),
+            cash("Withdraw", "2023-01-04", 10),
+            cash("Deposit", "2023-01-05", 200),
+            cash("Withdraw", "2023-01-06", 200),
+            cash("Deposit", "2023-01-06", 100),
+            cash("Withdraw", "2023-01-07", 50),
+        ]
+        .concat();
+
+        assert_eq!(
+            findings(&yaml),
+            vec![
+                "[negative-cash] portfolio port: assets:cash THB goes negative to -50 on 2023-01-03",
+                "[negative-cash] portfolio port: assets:cash THB goes negative to -10 on 2023-01-07",
+            ]
+        );
+    }
+}